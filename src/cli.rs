@@ -0,0 +1,240 @@
+use std::path::PathBuf;
+
+use crate::config::{Config, ConfigPath, ConverterConfig};
+use crate::layout::{Layout, WindowSize};
+use crate::markdown::Options;
+
+pub const USAGE: &str = "\
+usage: entermd [FILE...] [--profile NAME] [--serve DIR] [--port PORT] [-o OUT]
+               [--export-on-save] [--window SIZE] [--layout LAYOUT]
+       entermd convert [--profile NAME] [FILE]
+       entermd build [--profile NAME] [SRC] [-o OUT]
+       entermd watch [--profile NAME] [SRC] [-o OUT]
+       entermd serve [--profile NAME] [SRC] [-o OUT] [--port PORT]
+
+options:
+  --profile NAME   markdown flavour: entermd (default), commonmark or gfm
+  -o, --out OUT    where `build`, `watch` and `serve` write the site (default: public),
+                   or where the editor exports pages (default: next to the markdown)
+  --export-on-save export the page every time the editor saves
+  --serve DIR      serve DIR from the editor, with the buffer live at /preview.html
+  --port PORT      port for `serve` and `--serve` (default: 8000)
+  --window SIZE    open the editor WIDTHxHEIGHT, maximized or fullscreen
+                   instead of how it was last closed
+  --layout LAYOUT  horizontal, vertical or editor (preview hidden)
+  -h, --help       show this message
+
+anything not given here comes from entermd.toml, in ~/.config/entermd/ or the project";
+
+const DEFAULT_PORT: u16 = 8000;
+
+pub enum Command {
+    /// open the live editor window
+    Editor(Box<EditorArgs>),
+    /// convert FILE (or stdin) and print the page to stdout
+    Convert(Option<PathBuf>),
+    /// convert a whole content tree SRC into OUT
+    Build { src: PathBuf, out: PathBuf },
+    /// build, then rebuild SRC into OUT on every change
+    Watch { src: PathBuf, out: PathBuf },
+    /// watch like `Watch`, serving OUT with live reload
+    Serve { src: PathBuf, out: PathBuf },
+    Help,
+}
+
+pub struct EditorArgs {
+    /// markdown to open, in the running editor if there is one
+    pub files: Vec<PathBuf>,
+    /// site directory to serve alongside the editor
+    pub serve: Option<PathBuf>,
+    pub export_dir: Option<PathBuf>,
+    pub export_on_save: bool,
+    /// overrides the remembered window size and state
+    pub window: Option<WindowSize>,
+    pub layout: Option<Layout>,
+    /// what the flags set, laid back over entermd.toml whenever the editor reloads it
+    pub overrides: Config,
+}
+
+pub struct Args {
+    pub command: Command,
+    pub options: Options,
+    pub port: u16,
+}
+
+/// Parse the command line, with `config` filling in whatever it leaves out.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I, config: &Config) -> Result<Args, String> {
+    let mut overrides = Config::default();
+    let mut help = false;
+    let mut serve = None;
+    let mut port = None;
+    let mut export_on_save = false;
+    let mut window = None;
+    let mut layout = None;
+    let mut positional = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            help = true;
+        } else if arg == "--export-on-save" {
+            export_on_save = true;
+        } else if let Some(name) = flag_value(&arg, &["--profile"], &mut args) {
+            let name = name?;
+            overrides.converter = ConverterConfig::only(&name)
+                .ok_or_else(|| format!("unknown profile '{}'", name))?;
+        } else if let Some(path) = flag_value(&arg, &["-o", "--out"], &mut args) {
+            overrides.output = Some(ConfigPath::from(PathBuf::from(path?)));
+        } else if let Some(path) = flag_value(&arg, &["--serve"], &mut args) {
+            serve = Some(PathBuf::from(path?));
+        } else if let Some(value) = flag_value(&arg, &["--window"], &mut args) {
+            let value = value?;
+            window = Some(WindowSize::parse(&value).ok_or_else(|| format!("invalid window size '{}'", value))?);
+        } else if let Some(value) = flag_value(&arg, &["--layout"], &mut args) {
+            let value = value?;
+            layout = Some(Layout::from_name(&value).ok_or_else(|| format!("unknown layout '{}'", value))?);
+        } else if let Some(value) = flag_value(&arg, &["--port"], &mut args) {
+            let value = value?;
+            port = Some(value.parse().map_err(|_| format!("invalid port '{}'", value))?);
+        } else if arg.starts_with('-') && arg != "-" {
+            return Err(format!("unknown option '{}'", arg));
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    let merged = config.clone().merge(overrides.clone());
+    let options = merged.options();
+    let out = merged.output.as_ref().map(ConfigPath::resolved);
+    if help {
+        return Ok(Args { command: Command::Help, options, port: DEFAULT_PORT });
+    }
+
+    let mut positional = positional.into_iter().peekable();
+    let command = match positional.peek().cloned().as_deref() {
+        Some("convert") => {
+            positional.next();
+            // "-" means stdin, same as leaving the file out
+            let input = positional.next().filter(|path| path != "-").map(PathBuf::from);
+            if overrides.output.is_some() {
+                return Err("--out doesn't apply to `convert`".to_string());
+            }
+            Command::Convert(input)
+        }
+        Some(name @ ("build" | "watch" | "serve")) => {
+            positional.next();
+            let src = positional.next().map_or_else(|| PathBuf::from("."), PathBuf::from);
+            let out = out.unwrap_or_else(|| PathBuf::from("public"));
+            match name {
+                "build" => Command::Build { src, out },
+                "watch" => Command::Watch { src, out },
+                _ => Command::Serve { src, out },
+            }
+        }
+        // anything else is markdown for the editor (`./build` for a file called build)
+        _ => Command::Editor(Box::new(EditorArgs {
+            files: positional.by_ref().map(PathBuf::from).collect(),
+            serve: serve.take(),
+            export_dir: out,
+            export_on_save: std::mem::take(&mut export_on_save),
+            window: window.take(),
+            layout: layout.take(),
+            overrides,
+        })),
+    };
+    if let Some(extra) = positional.next() {
+        return Err(format!("unexpected argument '{}'", extra));
+    }
+    if serve.is_some() || export_on_save || window.is_some() || layout.is_some() {
+        return Err("--serve, --export-on-save, --window and --layout only apply to the editor".to_string());
+    }
+    let serves = match &command {
+        Command::Serve { .. } => true,
+        Command::Editor(args) => args.serve.is_some(),
+        _ => false,
+    };
+    if port.is_some() && !serves {
+        return Err("--port only applies to `serve` and `--serve`".to_string());
+    }
+
+    Ok(Args { command, options, port: port.unwrap_or(DEFAULT_PORT) })
+}
+
+// the value of `--name VALUE` or `--name=VALUE`, `None` when `arg` is some other flag
+fn flag_value(arg: &str, names: &[&str], args: &mut impl Iterator<Item = String>) -> Option<Result<String, String>> {
+    for name in names {
+        if arg == *name {
+            return Some(args.next().ok_or_else(|| format!("{} needs a value", name)));
+        }
+        if let Some(value) = arg.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(Ok(value.to_string()));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()), &Config::default())
+    }
+
+    #[test]
+    fn bare_arguments_open_the_editor() {
+        let args = parse(&["notes.md", "--window=800x600", "--layout", "vertical", "--export-on-save"]).unwrap();
+        let Command::Editor(editor) = args.command else { panic!("expected the editor") };
+        assert_eq!(editor.files, vec![PathBuf::from("notes.md")]);
+        assert_eq!(editor.window, Some(WindowSize::Size(800, 600)));
+        assert_eq!(editor.layout, Some(Layout::Vertical));
+        assert!(editor.export_on_save);
+        assert_eq!(args.options, Options::default());
+    }
+
+    #[test]
+    fn site_commands_default_to_here_and_public() {
+        let Command::Build { src, out } = parse(&["build"]).unwrap().command else { panic!("expected build") };
+        assert_eq!((src, out), (PathBuf::from("."), PathBuf::from("public")));
+        let args = parse(&["serve", "docs", "-o", "site", "--port", "9000"]).unwrap();
+        let Command::Serve { src, out } = args.command else { panic!("expected serve") };
+        assert_eq!((src, out, args.port), (PathBuf::from("docs"), PathBuf::from("site"), 9000));
+    }
+
+    #[test]
+    fn flags_go_over_the_config() {
+        let config = Config {
+            output: Some(ConfigPath::from(PathBuf::from("from-config"))),
+            converter: ConverterConfig::only("commonmark").unwrap(),
+            ..Config::default()
+        };
+        let args = parse_args(["build", "--profile=gfm"].map(String::from), &config).unwrap();
+        assert_eq!(args.options, Options::gfm());
+        let Command::Build { out, .. } = args.command else { panic!("expected build") };
+        assert_eq!(out, PathBuf::from("from-config"));
+    }
+
+    #[test]
+    fn flags_only_where_they_apply() {
+        assert!(parse(&["build", "--port", "9000"]).is_err());
+        assert!(parse(&["notes.md", "--port", "9000"]).is_err());
+        assert!(parse(&["notes.md", "--serve", "site", "--port", "9000"]).is_ok());
+        assert!(parse(&["convert", "-o", "page.html"]).is_err());
+        assert!(parse(&["watch", "--layout", "editor"]).is_err());
+        assert!(parse(&["convert", "a.md", "b.md"]).is_err());
+    }
+
+    #[test]
+    fn bad_values_are_errors() {
+        assert!(parse(&["--profile", "markdown2"]).is_err());
+        assert!(parse(&["--window", "800"]).is_err());
+        assert!(parse(&["--port", "http"]).is_err());
+        assert!(parse(&["--profile"]).is_err());
+        assert!(parse(&["--frobnicate"]).is_err());
+    }
+
+    #[test]
+    fn help_wins() {
+        assert!(matches!(parse(&["build", "--help"]).unwrap().command, Command::Help));
+    }
+}
@@ -0,0 +1,313 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{value, DocumentMut, Item, Table};
+
+use crate::markdown::Options;
+use crate::settings::config_dir;
+use crate::template::HTML_TEMPLATE;
+
+const CONFIG_FILE: &str = "entermd.toml";
+
+/// `entermd.toml`, from the user's config directory with the project's on top.
+///
+/// Everything is optional; whatever's left out keeps its built-in default.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// page template for the editor and `convert`, instead of the built-in one
+    pub template: Option<ConfigPath>,
+    /// where the editor exports pages and `build`/`watch`/`serve` write the site
+    pub output: Option<ConfigPath>,
+    /// light, dark, system (the default) or a user theme; the theme menu sets it too
+    pub theme: Option<String>,
+    /// milliseconds typing has to pause before the preview catches up
+    pub update_delay: Option<u64>,
+    pub converter: ConverterConfig,
+    pub fonts: FontConfig,
+    /// action to shortcuts, like `"win.save" = ["<Control>s"]`
+    pub keybindings: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConverterConfig {
+    /// entermd, commonmark or gfm; the extensions below change it further
+    pub profile: Option<String>,
+    pub tables: Option<bool>,
+    pub strikethrough: Option<bool>,
+    pub image_size: Option<bool>,
+    pub dash_replacement: Option<bool>,
+}
+
+impl ConverterConfig {
+    /// Exactly the `name` flavour, whatever a config underneath turns on or off.
+    pub fn only(name: &str) -> Option<Self> {
+        let options = Options::from_profile(name)?;
+        Some(ConverterConfig {
+            profile: Some(name.to_string()),
+            tables: Some(options.tables),
+            strikethrough: Some(options.strikethrough),
+            image_size: Some(options.image_size),
+            dash_replacement: Some(options.dash_replacement),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FontConfig {
+    /// pango font names, like "Iosevka 13"
+    pub editor: Option<String>,
+    pub preview: Option<String>,
+}
+
+/// A path as written in a config file, which is what gets saved back, along with
+/// the directory it's relative to.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "PathBuf")]
+pub struct ConfigPath {
+    pub written: PathBuf,
+    // the directory of the file it came from, empty for the working directory
+    dir: PathBuf,
+}
+
+impl From<PathBuf> for ConfigPath {
+    fn from(written: PathBuf) -> Self {
+        ConfigPath { written, dir: PathBuf::new() }
+    }
+}
+
+impl ConfigPath {
+    /// The path to use, relative paths being relative to the config file.
+    pub fn resolved(&self) -> PathBuf {
+        self.dir.join(&self.written)
+    }
+}
+
+/// The user-level config file.
+pub fn user_path() -> PathBuf {
+    config_dir().join(CONFIG_FILE)
+}
+
+/// The nearest `entermd.toml` from the working directory up, other than the user's own.
+pub fn project_path() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|path| path.is_file() && *path != user_path())
+}
+
+impl Config {
+    /// The user's config with the project's on top. Files that don't parse are
+    /// reported and left out.
+    pub fn load() -> Self {
+        let mut config = Config::load_user();
+        if let Some(path) = project_path() {
+            match Config::load_file(&path) {
+                Ok(project) => config = config.merge(project),
+                Err(err) => eprintln!("entermd: {}", err),
+            }
+        }
+        config
+    }
+
+    /// Just the user-level config, which is what the preferences dialog edits.
+    pub fn load_user() -> Self {
+        let path = user_path();
+        if !path.exists() {
+            return Config::default();
+        }
+        Config::load_file(&path).unwrap_or_else(|err| {
+            eprintln!("entermd: {}", err);
+            Config::default()
+        })
+    }
+
+    fn load_file(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let mut config: Config =
+            toml_edit::de::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+
+        // paths in the file are relative to it
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for path in [&mut config.template, &mut config.output].into_iter().flatten() {
+            path.dir = dir.to_path_buf();
+        }
+        Ok(config)
+    }
+
+    /// This config with `other`'s settings winning wherever it has them.
+    pub fn merge(self, other: Config) -> Config {
+        let mut keybindings = self.keybindings;
+        keybindings.extend(other.keybindings);
+        Config {
+            template: other.template.or(self.template),
+            output: other.output.or(self.output),
+            theme: other.theme.or(self.theme),
+            update_delay: other.update_delay.or(self.update_delay),
+            converter: ConverterConfig {
+                profile: other.converter.profile.or(self.converter.profile),
+                tables: other.converter.tables.or(self.converter.tables),
+                strikethrough: other.converter.strikethrough.or(self.converter.strikethrough),
+                image_size: other.converter.image_size.or(self.converter.image_size),
+                dash_replacement: other.converter.dash_replacement.or(self.converter.dash_replacement),
+            },
+            fonts: FontConfig {
+                editor: other.fonts.editor.or(self.fonts.editor),
+                preview: other.fonts.preview.or(self.fonts.preview),
+            },
+            keybindings,
+        }
+    }
+
+    /// The converter options the profile and extension settings add up to.
+    pub fn options(&self) -> Options {
+        let converter = &self.converter;
+        let mut options = match &converter.profile {
+            Some(name) => Options::from_profile(name).unwrap_or_else(|| {
+                eprintln!("entermd: unknown profile '{}' in {}", name, CONFIG_FILE);
+                Options::default()
+            }),
+            None => Options::default(),
+        };
+        options.tables = converter.tables.unwrap_or(options.tables);
+        options.strikethrough = converter.strikethrough.unwrap_or(options.strikethrough);
+        options.image_size = converter.image_size.unwrap_or(options.image_size);
+        options.dash_replacement = converter.dash_replacement.unwrap_or(options.dash_replacement);
+        options
+    }
+
+    /// The configured template, or the built-in one if there isn't one or it can't be read.
+    pub fn html_template(&self) -> String {
+        let Some(path) = self.template.as_ref().map(ConfigPath::resolved) else {
+            return HTML_TEMPLATE.to_string();
+        };
+        match fs::read_to_string(&path) {
+            Ok(template) if template.contains("{}") => template,
+            Ok(_) => {
+                eprintln!("entermd: {}: no {{}} for the page to go in, using the built-in template", path.display());
+                HTML_TEMPLATE.to_string()
+            }
+            Err(err) => {
+                eprintln!("entermd: {}: {}, using the built-in template", path.display(), err);
+                HTML_TEMPLATE.to_string()
+            }
+        }
+    }
+
+    /// Write everything but the keybindings into the user-level file, keeping
+    /// the rest of it (comments and keybindings included) as it was.
+    pub fn save_user(&self) -> Result<(), String> {
+        edit_user_file(|root| {
+            set(root, "template", self.template.as_ref().map(|path| path.written.display().to_string()));
+            set(root, "output", self.output.as_ref().map(|path| path.written.display().to_string()));
+            set(root, "theme", self.theme.clone());
+            set(root, "update-delay", self.update_delay.map(|delay| delay as i64));
+
+            let converter = section(root, "converter");
+            set(converter, "profile", self.converter.profile.clone());
+            set(converter, "tables", self.converter.tables);
+            set(converter, "strikethrough", self.converter.strikethrough);
+            set(converter, "image-size", self.converter.image_size);
+            set(converter, "dash-replacement", self.converter.dash_replacement);
+
+            let fonts = section(root, "fonts");
+            set(fonts, "editor", self.fonts.editor.clone());
+            set(fonts, "preview", self.fonts.preview.clone());
+        })
+    }
+
+    /// Write just the theme into the user-level file, for the theme menu.
+    pub fn save_user_theme(theme: &str) -> Result<(), String> {
+        edit_user_file(|root| set(root, "theme", (theme != "system").then(|| theme.to_string())))
+    }
+}
+
+// change the user-level file, keeping the rest of it (comments included) as it was
+fn edit_user_file(edit: impl FnOnce(&mut Table)) -> Result<(), String> {
+    let path = user_path();
+    let text = fs::read_to_string(&path).unwrap_or_default();
+    let mut doc: DocumentMut = text.parse().map_err(|err| format!("{}: {}", path.display(), err))?;
+    edit(doc.as_table_mut());
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+    }
+    fs::write(&path, doc.to_string()).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+// the `[name]` table, made if it isn't there (and left out of the file while it's empty)
+fn section<'a>(root: &'a mut Table, name: &str) -> &'a mut Table {
+    if !root.get(name).is_some_and(Item::is_table) {
+        let mut table = Table::new();
+        table.set_implicit(true);
+        root.insert(name, Item::Table(table));
+    }
+    root[name].as_table_mut().expect("just made a table")
+}
+
+// set or clear `key`, leaving a value that's already right alone so its comment survives
+fn set<V: Into<toml_edit::Value>>(table: &mut Table, key: &str, new: Option<V>) {
+    match new {
+        Some(new) => {
+            let new = new.into();
+            let same = table.get(key).and_then(Item::as_value).is_some_and(|old| {
+                let mut old = old.clone();
+                old.decor_mut().clear();
+                old.to_string() == new.to_string()
+            });
+            if !same {
+                table.insert(key, value(new));
+            }
+        }
+        None => {
+            table.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn parse(text: &str) -> Config {
+        toml_edit::de::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn merge_prefers_the_other_config() {
+        let user = parse("theme = \"dark\"\nupdate-delay = 100\n[converter]\ntables = false\n[keybindings]\n\"win.save\" = [\"<Control>s\"]\n");
+        let project = parse("theme = \"light\"\n[converter]\nprofile = \"gfm\"\n[keybindings]\n\"win.open\" = [\"<Control>o\"]\n");
+        let config = user.merge(project);
+        assert_eq!(config.theme.as_deref(), Some("light"));
+        assert_eq!(config.update_delay, Some(100));
+        assert_eq!(config.converter.profile.as_deref(), Some("gfm"));
+        assert_eq!(config.converter.tables, Some(false));
+        assert_eq!(config.keybindings.len(), 2);
+    }
+
+    #[test]
+    fn options_are_the_profile_with_extensions_on_top() {
+        assert_eq!(Config::default().options(), Options::default());
+        let config = parse("[converter]\nprofile = \"commonmark\"\nstrikethrough = true\n");
+        assert_eq!(config.options(), Options { strikethrough: true, ..Options::commonmark() });
+        // a flavour from the command line wins over the extensions too
+        let pinned = Config { converter: ConverterConfig::only("gfm").unwrap(), ..Config::default() };
+        assert_eq!(parse("[converter]\ntables = false\n").merge(pinned).options(), Options::gfm());
+    }
+
+    #[test]
+    fn paths_are_kept_as_written() {
+        let dir = TempDir::new("config");
+        dir.write(CONFIG_FILE, "output = \"public\"\ntemplate = \"/srv/page.html\"\n");
+        let config = Config::load_file(&dir.join(CONFIG_FILE)).unwrap();
+        let output = config.output.unwrap();
+        assert_eq!(output.written, PathBuf::from("public"));
+        assert_eq!(output.resolved(), dir.join("public"));
+        assert_eq!(config.template.unwrap().resolved(), PathBuf::from("/srv/page.html"));
+    }
+}
@@ -0,0 +1,324 @@
+use gtk4::prelude::*;
+use gtk4::gio::{Menu, SimpleAction};
+use gtk4::{
+    Box as GtkBox, Button, GestureClick, Label, Orientation, ScrolledWindow, Stack, TextBuffer, TextView, WrapMode,
+};
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+
+use crate::markdown::{Heading, IncrementalConverter, SourceBlock};
+use crate::template::{render_page, DEFAULT_ROOT};
+use crate::{highlight, preview, MarkdownEditor};
+
+/// One tab: a markdown file (or an untitled one) and its preview.
+pub struct Document {
+    // the notebook page, holding the markdown view
+    pub page: ScrolledWindow,
+    pub text_view: TextView,
+    pub md_buffer: TextBuffer,
+    pub html_view: TextView,
+    pub html_buffer: TextBuffer,
+    // the HTML source and the rendered page, switched by the preview toggle
+    pub preview: Stack,
+    // file name in the tab, with a `*` while there are unsaved changes
+    label: Label,
+    // which markdown lines each block of the HTML pane came from
+    pub source_map: Rc<RefCell<Vec<SourceBlock>>>,
+    // bumped on every edit, so conversions can tell when they've been overtaken
+    pub generation: Arc<AtomicU64>,
+    pub current_file: Rc<RefCell<Option<PathBuf>>>,
+    // converts the buffer on a worker thread, remembering blocks between edits
+    pub converter: Arc<Mutex<IncrementalConverter>>,
+    // set while one pane is scrolled to match the other, so it doesn't move the first one back
+    pub syncing: Cell<bool>,
+    // what looked wrong in the markdown at the last conversion, for the project sidebar
+    pub problem: RefCell<Option<String>>,
+    // headings as of the last conversion
+    pub outline: RefCell<Vec<Heading>>,
+}
+
+impl Document {
+    /// The file name, or "Untitled".
+    pub fn name(&self) -> String {
+        self.current_file.borrow().as_ref()
+            .and_then(|path| path.file_name())
+            .map_or_else(|| "Untitled".to_string(), |name| name.to_string_lossy().into_owned())
+    }
+
+    // nothing in it worth keeping, so opening a file can reuse the tab
+    fn is_pristine(&self) -> bool {
+        self.current_file.borrow().is_none() && !self.md_buffer.is_modified() && self.md_buffer.char_count() == 0
+    }
+
+    pub fn update_label(&self) {
+        let dirty = if self.md_buffer.is_modified() { "*" } else { "" };
+        self.label.set_text(&format!("{}{}", dirty, self.name()));
+        let path = self.current_file.borrow().as_ref().map(|path| path.display().to_string());
+        self.label.set_tooltip_text(path.as_deref());
+    }
+}
+
+impl MarkdownEditor {
+    /// The document in the current tab.
+    pub(crate) fn doc(&self) -> Rc<Document> {
+        let page = self.notebook.nth_page(self.notebook.current_page());
+        let documents = self.documents.borrow();
+        documents.iter()
+            .find(|doc| page.as_ref() == Some(doc.page.upcast_ref()))
+            .or(documents.first())
+            .cloned()
+            .expect("the editor always has a document")
+    }
+
+    /// Every open document, in no particular order.
+    pub(crate) fn documents(&self) -> Vec<Rc<Document>> {
+        self.documents.borrow().clone()
+    }
+
+    /// Open an empty tab and switch to it.
+    pub(crate) fn new_document(self: &Rc<Self>) -> Rc<Document> {
+        let text_view = TextView::new();
+        text_view.set_widget_name("markdown_input");
+        text_view.set_wrap_mode(WrapMode::Word); // Wrap at word boundaries
+        text_view.set_accepts_tab(true);
+        text_view.set_extra_menu(Some(&self.context_menu));
+        let md_buffer = text_view.buffer();
+        highlight::highlight_markdown(&md_buffer);
+        let page = ScrolledWindow::new();
+        page.set_child(Some(&text_view));
+
+        let html_view = TextView::new();
+        html_view.set_widget_name("static_html");
+        html_view.set_editable(false); // Make it read-only
+        html_view.set_can_focus(false); // Prevent focus on HTML view
+        html_view.set_wrap_mode(WrapMode::None); // Allow horizontal scrolling
+        // padding to make HTML view nicer
+        html_view.set_left_margin(10);
+        html_view.set_right_margin(10);
+        html_view.set_top_margin(10);
+        html_view.set_bottom_margin(10);
+        let html_buffer = html_view.buffer();
+        highlight::highlight_html(&html_buffer);
+        highlight::set_dark(&md_buffer, self.dark.get());
+        highlight::set_dark(&html_buffer, self.dark.get());
+        let scrolled_html = ScrolledWindow::new();
+        scrolled_html.set_child(Some(&html_view));
+
+        // rendered preview, swapped in for the HTML source by the preview toggle
+        let current_file = Rc::new(RefCell::new(None));
+        let rendered_view = preview::rendered_view(&html_buffer, current_file.clone());
+        let click_controller = GestureClick::new();
+        let text_view_clone = text_view.clone();
+        click_controller.connect_pressed(move |_, _, _, _| {
+            text_view_clone.grab_focus();
+        });
+        rendered_view.add_controller(click_controller);
+        let scrolled_rendered = ScrolledWindow::new();
+        scrolled_rendered.set_child(Some(&rendered_view));
+
+        let preview = Stack::new();
+        preview.add_named(&scrolled_html, Some("source"));
+        preview.add_named(&scrolled_rendered, Some("rendered"));
+        preview.set_visible_child_name(if self.rendered.get() { "rendered" } else { "source" });
+
+        // initial empty HTML structure
+        html_buffer.set_text(&render_page(&self.html_template.borrow(), "", DEFAULT_ROOT));
+
+        let label = Label::new(None);
+        let close = Button::from_icon_name("window-close-symbolic");
+        close.set_has_frame(false);
+        close.set_focus_on_click(false);
+        close.set_tooltip_text(Some("Close (Ctrl+W)"));
+        let tab = GtkBox::new(Orientation::Horizontal, 4);
+        tab.append(&label);
+        tab.append(&close);
+
+        let doc = Rc::new(Document {
+            page,
+            text_view,
+            md_buffer,
+            html_view,
+            html_buffer,
+            preview,
+            label,
+            source_map: Rc::new(RefCell::new(Vec::new())),
+            generation: Arc::new(AtomicU64::new(0)),
+            current_file,
+            converter: Arc::new(Mutex::new(IncrementalConverter::new(self.options.get()))),
+            syncing: Cell::new(false),
+            problem: RefCell::new(None),
+            outline: RefCell::new(Vec::new()),
+        });
+        doc.update_label();
+
+        let editor = self.clone();
+        let weak = Rc::downgrade(&doc);
+        close.connect_clicked(move |_| {
+            if let Some(doc) = weak.upgrade() {
+                editor.close_documents(vec![doc]);
+            }
+        });
+
+        // the tab and title show the file name, with a `*` while there are unsaved changes
+        let editor = self.clone();
+        let weak = Rc::downgrade(&doc);
+        doc.md_buffer.connect_modified_changed(move |_| {
+            if let Some(doc) = weak.upgrade() {
+                doc.update_label();
+                editor.update_title();
+                editor.update_file_status();
+            }
+        });
+
+        self.documents.borrow_mut().push(doc.clone());
+        self.previews.add_child(&doc.preview);
+        self.connect_document_updates(&doc);
+        self.connect_document_source_map(&doc);
+        self.connect_document_scroll_sync(&doc);
+        self.connect_list_editing(&doc);
+        self.connect_document_search(&doc);
+
+        let index = self.notebook.append_page(&doc.page, Some(&tab));
+        self.notebook.set_current_page(Some(index));
+        doc.text_view.grab_focus();
+        doc
+    }
+
+    /// Show `path` in its tab if it's open already, otherwise open it in a new one.
+    pub(crate) fn open_path(self: &Rc<Self>, path: &Path) {
+        let open = self.documents().into_iter().find(|doc| doc.current_file.borrow().as_deref() == Some(path));
+        if let Some(doc) = open {
+            self.show_document(&doc);
+            return;
+        }
+        let current = self.doc();
+        let doc = if current.is_pristine() { current } else { self.new_document() };
+        self.open_file(&doc, path);
+    }
+
+    pub(crate) fn show_document(&self, doc: &Document) {
+        if let Some(index) = self.notebook.page_num(&doc.page) {
+            self.notebook.set_current_page(Some(index));
+        }
+    }
+
+    /// Close `docs` one at a time, asking about unsaved changes; cancelling stops there.
+    pub(crate) fn close_documents(self: &Rc<Self>, docs: Vec<Rc<Document>>) {
+        let mut docs = docs.into_iter();
+        let Some(doc) = docs.next() else {
+            return;
+        };
+        let rest: Vec<_> = docs.collect();
+        // whoever's asked about it should be able to see it
+        self.show_document(&doc);
+        let editor = self.clone();
+        let closing = doc.clone();
+        self.confirm_discard(&doc, move || {
+            editor.remove_document(&closing);
+            editor.close_documents(rest.clone());
+        });
+    }
+
+    fn remove_document(self: &Rc<Self>, doc: &Rc<Document>) {
+        if let Some(index) = self.notebook.page_num(&doc.page) {
+            self.notebook.remove_page(Some(index));
+        }
+        self.previews.remove(&doc.preview);
+        self.documents.borrow_mut().retain(|open| !Rc::ptr_eq(open, doc));
+        self.update_file_status();
+        // there's always somewhere to type
+        if self.documents.borrow().is_empty() {
+            self.new_document();
+        }
+    }
+
+    /// Keep the preview, title and live page on the current tab, and the tab actions
+    /// with their shortcuts.
+    pub(crate) fn connect_tabs(self: &Rc<Self>) {
+        let editor = self.clone();
+        self.notebook.connect_switch_page(move |_, page, _| {
+            let Some(doc) = editor.documents().into_iter().find(|doc| doc.page.upcast_ref::<gtk4::Widget>() == page) else {
+                return;
+            };
+            editor.previews.set_visible_child(&doc.preview);
+            editor.update_title_for(&doc);
+            editor.show_outline(&doc);
+            editor.update_search(&doc);
+            if let Some(server) = &editor.preview_server {
+                let html = doc.html_buffer.text(&doc.html_buffer.start_iter(), &doc.html_buffer.end_iter(), false);
+                server.set_live_page(&html);
+            }
+            doc.text_view.grab_focus();
+        });
+
+        let new_tab = SimpleAction::new("new-tab", None);
+        let editor = self.clone();
+        new_tab.connect_activate(move |_, _| {
+            editor.new_document();
+        });
+        self.window.add_action(&new_tab);
+
+        let close_tab = SimpleAction::new("close-tab", None);
+        let editor = self.clone();
+        close_tab.connect_activate(move |_, _| editor.close_documents(vec![editor.doc()]));
+        self.window.add_action(&close_tab);
+
+        let close_others = SimpleAction::new("close-other-tabs", None);
+        let editor = self.clone();
+        close_others.connect_activate(move |_, _| {
+            let keep = editor.doc();
+            let others = editor.documents().into_iter().filter(|doc| !Rc::ptr_eq(doc, &keep)).collect();
+            editor.close_documents(others);
+        });
+        self.window.add_action(&close_others);
+
+        let next_tab = SimpleAction::new("next-tab", None);
+        let notebook = self.notebook.clone();
+        next_tab.connect_activate(move |_, _| {
+            let count = notebook.n_pages();
+            let current = notebook.current_page().unwrap_or(0);
+            notebook.set_current_page(Some((current + 1) % count.max(1)));
+        });
+        self.window.add_action(&next_tab);
+
+        let previous_tab = SimpleAction::new("previous-tab", None);
+        let notebook = self.notebook.clone();
+        previous_tab.connect_activate(move |_, _| {
+            let count = notebook.n_pages().max(1);
+            let current = notebook.current_page().unwrap_or(0);
+            notebook.set_current_page(Some((current + count - 1) % count));
+        });
+        self.window.add_action(&previous_tab);
+
+        let go_to_tab = SimpleAction::new("tab", Some(glib::VariantTy::INT32));
+        let notebook = self.notebook.clone();
+        go_to_tab.connect_activate(move |_, index| {
+            // Alt+9 is always the last tab, like in browsers
+            let count = notebook.n_pages();
+            match index.and_then(|index| index.get::<i32>()) {
+                Some(9) => notebook.set_current_page(Some(count.saturating_sub(1))),
+                Some(index) if index >= 1 && (index as u32) <= count => notebook.set_current_page(Some(index as u32 - 1)),
+                _ => {}
+            }
+        });
+        self.window.add_action(&go_to_tab);
+
+        if let Some(app) = self.window.application() {
+            app.set_accels_for_action("win.new-tab", &["<Control>t", "<Control>n"]);
+            app.set_accels_for_action("win.close-tab", &["<Control>w"]);
+            app.set_accels_for_action("win.next-tab", &["<Control>Page_Down", "<Control>Tab"]);
+            app.set_accels_for_action("win.previous-tab", &["<Control>Page_Up", "<Control><Shift>Tab"]);
+            for index in 1..=9 {
+                app.set_accels_for_action(&format!("win.tab({})", index), &[&format!("<Alt>{}", index)]);
+            }
+        }
+
+        let tabs = Menu::new();
+        tabs.append(Some("New Tab"), Some("win.new-tab"));
+        tabs.append(Some("Close Other Tabs"), Some("win.close-other-tabs"));
+        self.context_menu.append_section(None, &tabs);
+    }
+}
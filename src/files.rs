@@ -0,0 +1,298 @@
+use gtk4::prelude::*;
+use gtk4::{
+    ButtonsType, FileChooserAction, FileChooserDialog, FileFilter, MessageDialog, MessageType,
+    ResponseType,
+};
+use gtk4::gio::{self, Menu, SimpleAction};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::document::Document;
+use crate::{render_html, MarkdownEditor};
+
+const APP_TITLE: &str = "Markdown -> HTML *Attempt*";
+
+impl MarkdownEditor {
+    /// Open/save/save-as actions, their shortcuts and the unsaved-changes prompt on close.
+    pub(crate) fn connect_file_actions(self: &Rc<Self>) {
+        let open = SimpleAction::new("open", None);
+        let editor = self.clone();
+        open.connect_activate(move |_, _| editor.choose_file_to_open());
+        self.window.add_action(&open);
+
+        let save = SimpleAction::new("save", None);
+        let editor = self.clone();
+        save.connect_activate(move |_, _| editor.save(&editor.doc(), || {}));
+        self.window.add_action(&save);
+
+        let save_as = SimpleAction::new("save-as", None);
+        let editor = self.clone();
+        save_as.connect_activate(move |_, _| editor.save_as(&editor.doc(), || {}));
+        self.window.add_action(&save_as);
+
+        let export = SimpleAction::new("export-html", None);
+        let editor = self.clone();
+        export.connect_activate(move |_, _| editor.export_html(&editor.doc()));
+        self.window.add_action(&export);
+
+        let export_on_save = SimpleAction::new_stateful("export-on-save", None, &self.export_on_save.get().to_variant());
+        let editor = self.clone();
+        export_on_save.connect_activate(move |action, _| {
+            let enabled = !editor.export_on_save.get();
+            editor.export_on_save.set(enabled);
+            action.set_state(&enabled.to_variant());
+        });
+        self.window.add_action(&export_on_save);
+
+        if let Some(app) = self.window.application() {
+            app.set_accels_for_action("win.open", &["<Control>o"]);
+            app.set_accels_for_action("win.save", &["<Control>s"]);
+            app.set_accels_for_action("win.save-as", &["<Control><Shift>s"]);
+            app.set_accels_for_action("win.export-html", &["<Control>e"]);
+        }
+
+        // exporting lives in the markdown pane's right-click menu
+        let export_menu = Menu::new();
+        export_menu.append(Some("Export HTML"), Some("win.export-html"));
+        export_menu.append(Some("Export on Save"), Some("win.export-on-save"));
+        self.context_menu.append_section(None, &export_menu);
+
+        // asks about each tab with unsaved changes in turn, cancelling any of them keeps the window
+        let editor = self.clone();
+        self.window.connect_close_request(move |window| {
+            let Some(doc) = editor.documents().into_iter().find(|doc| doc.md_buffer.is_modified()) else {
+                return glib::Propagation::Proceed;
+            };
+            editor.show_document(&doc);
+            let closing = doc.clone();
+            let window = window.clone();
+            editor.confirm_discard(&doc, move || {
+                // saved or thrown away, either way there's nothing left to ask about
+                closing.md_buffer.set_modified(false);
+                window.close();
+            });
+            glib::Propagation::Stop
+        });
+    }
+
+    /// The title shows the current tab's file name, with a `*` while there are unsaved changes.
+    pub(crate) fn update_title(&self) {
+        self.update_title_for(&self.doc());
+    }
+
+    // for while the notebook is still switching to `doc`
+    pub(crate) fn update_title_for(&self, doc: &Document) {
+        let dirty = if doc.md_buffer.is_modified() { "*" } else { "" };
+        self.window.set_title(Some(&format!("{}{} - {}", dirty, doc.name(), APP_TITLE)));
+    }
+
+    /// Run `then` straight away, or once the user has saved or discarded the document's changes.
+    pub(crate) fn confirm_discard(self: &Rc<Self>, doc: &Rc<Document>, then: impl Fn() + 'static) {
+        if !doc.md_buffer.is_modified() {
+            then();
+            return;
+        }
+
+        let dialog = MessageDialog::builder()
+            .transient_for(&self.window)
+            .modal(true)
+            .message_type(MessageType::Question)
+            .buttons(ButtonsType::None)
+            .text(format!("Save changes to {}?", doc.name()))
+            .secondary_text("Your changes will be lost if you don't save them.")
+            .build();
+        dialog.add_buttons(&[
+            ("_Cancel", ResponseType::Cancel),
+            ("_Discard", ResponseType::Reject),
+            ("_Save", ResponseType::Accept),
+        ]);
+        dialog.set_default_response(ResponseType::Accept);
+
+        let then = Rc::new(then);
+        let editor = self.clone();
+        let doc = doc.clone();
+        dialog.connect_response(move |dialog, response| {
+            dialog.close();
+            match response {
+                ResponseType::Accept => {
+                    let then = then.clone();
+                    editor.save(&doc, move || then());
+                }
+                ResponseType::Reject => then(),
+                _ => {}
+            }
+        });
+        dialog.present();
+    }
+
+    fn choose_file_to_open(self: &Rc<Self>) {
+        let dialog = self.file_chooser("Open Markdown", FileChooserAction::Open, "_Open");
+        let editor = self.clone();
+        dialog.connect_response(move |dialog, response| {
+            let path = dialog.file().and_then(|file| file.path());
+            dialog.close();
+            if let (ResponseType::Accept, Some(path)) = (response, path) {
+                editor.open_path(&path);
+            }
+        });
+        dialog.present();
+    }
+
+    /// Open files handed over from the command line or another `entermd`, a tab each.
+    pub(crate) fn open_files(self: &Rc<Self>, files: &[gio::File]) {
+        for path in files.iter().filter_map(|file| file.path()) {
+            self.open_path(&path);
+        }
+    }
+
+    pub(crate) fn open_file(&self, doc: &Document, path: &Path) {
+        match fs::read_to_string(path) {
+            Ok(markdown) => {
+                doc.md_buffer.set_text(&markdown);
+                doc.md_buffer.place_cursor(&doc.md_buffer.start_iter());
+                *doc.current_file.borrow_mut() = Some(path.to_path_buf());
+                doc.md_buffer.set_modified(false);
+                doc.update_label();
+                self.update_title_for(doc);
+                self.update_file_status();
+            }
+            Err(err) => self.show_error(&format!("Couldn't open {}", path.display()), &err.to_string()),
+        }
+    }
+
+    /// Save to the current file, asking for one first if there isn't one yet.
+    fn save(self: &Rc<Self>, doc: &Rc<Document>, on_saved: impl Fn() + 'static) {
+        let path = doc.current_file.borrow().clone();
+        match path {
+            Some(path) => {
+                if self.write_file(doc, &path) {
+                    on_saved();
+                }
+            }
+            None => self.save_as(doc, on_saved),
+        }
+    }
+
+    fn save_as(self: &Rc<Self>, doc: &Rc<Document>, on_saved: impl Fn() + 'static) {
+        let dialog = self.file_chooser("Save Markdown", FileChooserAction::Save, "_Save");
+        let current_name = doc.current_file.borrow().as_ref()
+            .and_then(|path| path.file_name())
+            .map_or_else(|| "untitled.md".to_string(), |name| name.to_string_lossy().into_owned());
+        dialog.set_current_name(&current_name);
+
+        let editor = self.clone();
+        let doc = doc.clone();
+        dialog.connect_response(move |dialog, response| {
+            let path = dialog.file().and_then(|file| file.path());
+            dialog.close();
+            if let (ResponseType::Accept, Some(path)) = (response, path) {
+                if editor.write_file(&doc, &path) {
+                    *doc.current_file.borrow_mut() = Some(path);
+                    doc.update_label();
+                    editor.update_title();
+                    editor.update_file_status();
+                    on_saved();
+                }
+            }
+        });
+        dialog.present();
+    }
+
+    // returns whether the file was written, errors are shown to the user
+    fn write_file(&self, doc: &Document, path: &Path) -> bool {
+        let markdown = doc.md_buffer.text(&doc.md_buffer.start_iter(), &doc.md_buffer.end_iter(), false);
+        match fs::write(path, markdown.as_str()) {
+            Ok(()) => {
+                doc.md_buffer.set_modified(false);
+                if self.export_on_save.get() {
+                    self.write_export(doc, path);
+                }
+                true
+            }
+            Err(err) => {
+                self.show_error(&format!("Couldn't save {}", path.display()), &err.to_string());
+                false
+            }
+        }
+    }
+
+    /// Write the page as shown in the HTML pane, saving the markdown first if it's never been saved.
+    fn export_html(self: &Rc<Self>, doc: &Rc<Document>) {
+        let source = doc.current_file.borrow().clone();
+        match source {
+            Some(source) => self.write_export(doc, &source),
+            None => {
+                // the page is named after its markdown, so that needs a name first
+                let editor = self.clone();
+                let saved = doc.clone();
+                self.save_as(doc, move || {
+                    if let Some(source) = saved.current_file.borrow().clone() {
+                        editor.write_export(&saved, &source);
+                    }
+                });
+            }
+        }
+    }
+
+    // `notes.md` exports to `notes.html`, next to it or in the export directory
+    fn export_path(&self, source: &Path) -> PathBuf {
+        let page = source.with_extension("html");
+        match (&*self.export_dir.borrow(), page.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name),
+            _ => page,
+        }
+    }
+
+    // overwrites any earlier export, errors are shown to the user
+    fn write_export(&self, doc: &Document, source: &Path) {
+        let target = self.export_path(source);
+        let html = render_html(&doc.md_buffer, &self.html_template.borrow(), &self.options.get());
+        let result = match target.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => fs::create_dir_all(dir),
+            _ => Ok(()),
+        };
+        if let Err(err) = result.and_then(|_| fs::write(&target, html)) {
+            self.show_error(&format!("Couldn't export {}", target.display()), &err.to_string());
+        }
+    }
+
+    fn file_chooser(&self, title: &str, action: FileChooserAction, accept: &str) -> FileChooserDialog {
+        let dialog = FileChooserDialog::new(
+            Some(title),
+            Some(&self.window),
+            action,
+            &[("_Cancel", ResponseType::Cancel), (accept, ResponseType::Accept)],
+        );
+        dialog.set_modal(true);
+
+        let markdown = FileFilter::new();
+        markdown.set_name(Some("Markdown"));
+        markdown.add_mime_type("text/markdown");
+        markdown.add_pattern("*.md");
+        dialog.add_filter(&markdown);
+        let all = FileFilter::new();
+        all.set_name(Some("All files"));
+        all.add_pattern("*");
+        dialog.add_filter(&all);
+
+        // start next to the file we're editing
+        if let Some(dir) = self.doc().current_file.borrow().as_ref().and_then(|path| path.parent()) {
+            let _ = dialog.set_current_folder(Some(&gio::File::for_path(dir)));
+        }
+        dialog
+    }
+
+    pub(crate) fn show_error(&self, text: &str, detail: &str) {
+        let dialog = MessageDialog::builder()
+            .transient_for(&self.window)
+            .modal(true)
+            .message_type(MessageType::Error)
+            .buttons(ButtonsType::Close)
+            .text(text)
+            .secondary_text(detail)
+            .build();
+        dialog.connect_response(|dialog, _| dialog.close());
+        dialog.present();
+    }
+}
@@ -0,0 +1,469 @@
+use gtk4::prelude::*;
+use gtk4::gdk::{Key, ModifierType};
+use gtk4::gio::SimpleAction;
+use gtk4::{
+    Box as GtkBox, Button, CheckButton, Entry, EventControllerKey, Label, Orientation, Revealer, SearchEntry,
+    TextBuffer, TextTag, TextView,
+};
+use regex::{Regex, RegexBuilder};
+use std::rc::Rc;
+
+use crate::document::Document;
+use crate::MarkdownEditor;
+
+const MATCH_TAG: &str = "search-match";
+// the match that's selected, which next/previous and replace go from
+const CURRENT_TAG: &str = "search-current";
+
+/// The find and replace bar under the editor, hidden until Ctrl+F or Ctrl+H.
+pub struct FindBar {
+    pub revealer: Revealer,
+    search: SearchEntry,
+    replace_row: GtkBox,
+    replacement: Entry,
+    match_case: CheckButton,
+    whole_word: CheckButton,
+    regex: CheckButton,
+    in_html: CheckButton,
+    count: Label,
+}
+
+// one match, in bytes of the text it was found in and in buffer characters
+struct Match {
+    bytes: (usize, usize),
+    chars: (i32, i32),
+}
+
+fn icon_button(icon: &str, tooltip: &str, action: &str) -> Button {
+    let button = Button::from_icon_name(icon);
+    button.set_tooltip_text(Some(tooltip));
+    button.set_focus_on_click(false);
+    button.set_action_name(Some(action));
+    button
+}
+
+/// The bar, hidden, for `MarkdownEditor::connect_find` to hook up.
+pub fn find_bar() -> FindBar {
+    let search = SearchEntry::new();
+    search.set_hexpand(true);
+    search.set_placeholder_text(Some("Find"));
+    let count = Label::new(None);
+    count.set_widget_name("find_count");
+    let match_case = CheckButton::with_label("Match case");
+    let whole_word = CheckButton::with_label("Whole word");
+    let regex = CheckButton::with_label("Regex");
+    let in_html = CheckButton::with_label("In HTML");
+    in_html.set_tooltip_text(Some("Search the HTML pane instead of the markdown"));
+
+    let find_row = GtkBox::new(Orientation::Horizontal, 6);
+    find_row.append(&search);
+    find_row.append(&icon_button("go-up-symbolic", "Previous match (Shift+F3)", "win.find-previous"));
+    find_row.append(&icon_button("go-down-symbolic", "Next match (F3)", "win.find-next"));
+    find_row.append(&count);
+    for check in [&match_case, &whole_word, &regex, &in_html] {
+        check.set_focus_on_click(false);
+        find_row.append(check);
+    }
+    find_row.append(&icon_button("window-close-symbolic", "Close (Escape)", "win.close-find"));
+
+    let replacement = Entry::new();
+    replacement.set_hexpand(true);
+    replacement.set_placeholder_text(Some("Replace with ($1 for a regex group)"));
+    let replace = Button::with_label("Replace");
+    replace.set_action_name(Some("win.replace"));
+    let replace_all = Button::with_label("Replace All");
+    replace_all.set_action_name(Some("win.replace-all"));
+    let replace_row = GtkBox::new(Orientation::Horizontal, 6);
+    replace_row.append(&replacement);
+    replace_row.append(&replace);
+    replace_row.append(&replace_all);
+
+    let bar = GtkBox::new(Orientation::Vertical, 6);
+    bar.set_widget_name("find_bar");
+    bar.append(&find_row);
+    bar.append(&replace_row);
+    let revealer = Revealer::new();
+    revealer.set_child(Some(&bar));
+
+    FindBar {
+        revealer,
+        search,
+        replace_row,
+        replacement,
+        match_case,
+        whole_word,
+        regex,
+        in_html,
+        count,
+    }
+}
+
+impl FindBar {
+    // what's typed in the search box as a regex, None while it's empty
+    fn pattern(&self) -> Option<Result<Regex, regex::Error>> {
+        let query = self.search.text();
+        if query.is_empty() {
+            return None;
+        }
+        let pattern = if self.regex.is_active() { query.to_string() } else { regex::escape(&query) };
+        Some(RegexBuilder::new(&pattern).case_insensitive(!self.match_case.is_active()).multi_line(true).build())
+    }
+
+    fn matches(&self, pattern: &Regex, text: &str) -> Vec<Match> {
+        find_all(pattern, text, self.whole_word.is_active())
+    }
+
+    // what a match gets replaced with; `$1` and `${name}` only mean something in regex mode
+    fn replacement_for(&self, pattern: &Regex, text: &str, found: &Match) -> String {
+        let replacement = self.replacement.text();
+        if !self.regex.is_active() {
+            return replacement.to_string();
+        }
+        let mut expanded = String::new();
+        if let Some(captures) = pattern.captures_at(text, found.bytes.0) {
+            captures.expand(&replacement, &mut expanded);
+        }
+        expanded
+    }
+}
+
+// every non-empty match of `pattern` in `text`; for `whole_word` only those that don't
+// start or end in the middle of a word, so "C++" and "#tag" work too
+fn find_all(pattern: &Regex, text: &str, whole_word: bool) -> Vec<Match> {
+    let mut matches = Vec::new();
+    // buffer offsets are in characters, counted up as we go
+    let (mut byte, mut offset) = (0, 0);
+    for found in pattern.find_iter(text).filter(|found| !found.is_empty()) {
+        if whole_word && (inside_word(text, found.start()) || inside_word(text, found.end())) {
+            continue;
+        }
+        let start = offset + text[byte..found.start()].chars().count() as i32;
+        let end = start + found.as_str().chars().count() as i32;
+        matches.push(Match { bytes: (found.start(), found.end()), chars: (start, end) });
+        (byte, offset) = (found.end(), end);
+    }
+    matches
+}
+
+// whether there are word characters on both sides of byte `at`
+fn inside_word(text: &str, at: usize) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    text[..at].chars().next_back().is_some_and(is_word) && text[at..].chars().next().is_some_and(is_word)
+}
+
+fn buffer_text(buffer: &TextBuffer) -> String {
+    buffer.text(&buffer.start_iter(), &buffer.end_iter(), false).to_string()
+}
+
+// the selection as character offsets, or the cursor twice
+fn selection(buffer: &TextBuffer) -> (i32, i32) {
+    match buffer.selection_bounds() {
+        Some((start, end)) => (start.offset(), end.offset()),
+        None => {
+            let cursor = buffer.iter_at_mark(&buffer.get_insert()).offset();
+            (cursor, cursor)
+        }
+    }
+}
+
+fn clear_matches(buffer: &TextBuffer) {
+    let (start, end) = buffer.bounds();
+    buffer.remove_tag_by_name(MATCH_TAG, &start, &end);
+    buffer.remove_tag_by_name(CURRENT_TAG, &start, &end);
+}
+
+impl MarkdownEditor {
+    /// Ctrl+F finds in the markdown (or the HTML pane), Ctrl+H replaces, F3 and
+    /// Shift+F3 step through the matches, Escape closes the bar.
+    pub(crate) fn connect_find(self: &Rc<Self>) {
+        let open_find = SimpleAction::new("find", None);
+        let editor = self.clone();
+        open_find.connect_activate(move |_, _| editor.show_find(false));
+        self.window.add_action(&open_find);
+
+        let find_replace = SimpleAction::new("find-replace", None);
+        let editor = self.clone();
+        find_replace.connect_activate(move |_, _| editor.show_find(true));
+        self.window.add_action(&find_replace);
+
+        let find_next = SimpleAction::new("find-next", None);
+        let editor = self.clone();
+        find_next.connect_activate(move |_, _| editor.find_match(true, false));
+        self.window.add_action(&find_next);
+
+        let find_previous = SimpleAction::new("find-previous", None);
+        let editor = self.clone();
+        find_previous.connect_activate(move |_, _| editor.find_match(false, false));
+        self.window.add_action(&find_previous);
+
+        let replace = SimpleAction::new("replace", None);
+        let editor = self.clone();
+        replace.connect_activate(move |_, _| editor.replace_match());
+        self.window.add_action(&replace);
+
+        let replace_all = SimpleAction::new("replace-all", None);
+        let editor = self.clone();
+        replace_all.connect_activate(move |_, _| editor.replace_all());
+        self.window.add_action(&replace_all);
+
+        let close = SimpleAction::new("close-find", None);
+        let editor = self.clone();
+        close.connect_activate(move |_, _| editor.close_find());
+        self.window.add_action(&close);
+
+        if let Some(app) = self.window.application() {
+            app.set_accels_for_action("win.find", &["<Control>f"]);
+            app.set_accels_for_action("win.find-replace", &["<Control>h"]);
+            app.set_accels_for_action("win.find-next", &["F3", "<Control>g"]);
+            app.set_accels_for_action("win.find-previous", &["<Shift>F3", "<Control><Shift>g"]);
+        }
+
+        let find = &self.find;
+        // typing searches from where the cursor is, like in a browser
+        let editor = self.clone();
+        find.search.connect_search_changed(move |_| editor.find_match(true, true));
+        let editor = self.clone();
+        find.search.connect_activate(move |_| editor.find_match(true, false));
+        let editor = self.clone();
+        find.search.connect_stop_search(move |_| editor.close_find());
+        let editor = self.clone();
+        find.replacement.connect_activate(move |_| editor.replace_match());
+
+        for check in [&find.match_case, &find.whole_word, &find.regex] {
+            let editor = self.clone();
+            check.connect_toggled(move |_| editor.find_match(true, true));
+        }
+        let editor = self.clone();
+        find.in_html.connect_toggled(move |in_html| {
+            // the html pane is generated, there's nothing to replace in it
+            editor.find.replace_row.set_sensitive(!in_html.is_active());
+            if in_html.is_active() && editor.rendered.get() {
+                ActionGroupExt::activate_action(&editor.window, "rendered-preview", None);
+            }
+            editor.find_match(true, true);
+        });
+
+        // Shift+Enter goes back, Escape from anywhere in the bar closes it
+        let keys = EventControllerKey::new();
+        let editor = self.clone();
+        keys.connect_key_pressed(move |_, key, _, modifiers| match key {
+            Key::Escape => {
+                editor.close_find();
+                glib::Propagation::Stop
+            }
+            Key::Return | Key::KP_Enter if modifiers.contains(ModifierType::SHIFT_MASK) => {
+                editor.find_match(false, false);
+                glib::Propagation::Stop
+            }
+            _ => glib::Propagation::Proceed,
+        });
+        find.revealer.add_controller(keys);
+    }
+
+    /// Match highlighting for a new document. Edits re-highlight along with the
+    /// preview, once they settle, rather than on every change.
+    pub(crate) fn connect_document_search(&self, doc: &Document) {
+        for buffer in [&doc.md_buffer, &doc.html_buffer] {
+            let table = buffer.tag_table();
+            table.add(&TextTag::builder().name(MATCH_TAG).background("rgba(255, 200, 0, 0.35)").build());
+            table.add(&TextTag::builder().name(CURRENT_TAG).background("rgba(255, 120, 0, 0.6)").build());
+        }
+    }
+
+    fn show_find(&self, replace: bool) {
+        let find = &self.find;
+        let doc = self.doc();
+        // a selection on one line is probably what's being looked for
+        if let Some((start, end)) = doc.md_buffer.selection_bounds() {
+            let selected = doc.md_buffer.text(&start, &end, false);
+            if !selected.contains('\n') && !find.in_html.is_active() {
+                find.search.set_text(&if find.regex.is_active() { regex::escape(&selected) } else { selected.to_string() });
+            }
+        }
+        find.replace_row.set_visible(replace);
+        find.revealer.set_reveal_child(true);
+        if replace && !find.search.text().is_empty() {
+            find.replacement.grab_focus();
+        } else {
+            find.search.grab_focus();
+        }
+        self.update_search(&doc);
+    }
+
+    fn close_find(&self) {
+        self.find.revealer.set_reveal_child(false);
+        for doc in self.documents() {
+            clear_matches(&doc.md_buffer);
+            clear_matches(&doc.html_buffer);
+        }
+        self.doc().text_view.grab_focus();
+    }
+
+    // the buffer being searched and the view showing it
+    fn search_target(&self, doc: &Document) -> (TextBuffer, TextView) {
+        if self.find.in_html.is_active() {
+            (doc.html_buffer.clone(), doc.html_view.clone())
+        } else {
+            (doc.md_buffer.clone(), doc.text_view.clone())
+        }
+    }
+
+    /// Highlight every match in `doc` and say how many there are.
+    pub(crate) fn update_search(&self, doc: &Document) {
+        clear_matches(&doc.md_buffer);
+        clear_matches(&doc.html_buffer);
+        let find = &self.find;
+        find.search.remove_css_class("error");
+        if !find.revealer.reveals_child() {
+            return;
+        }
+        let pattern = match find.pattern() {
+            None => {
+                find.count.set_text("");
+                return;
+            }
+            Some(Err(err)) => {
+                find.search.add_css_class("error");
+                find.count.set_text("invalid pattern");
+                find.count.set_tooltip_text(Some(&err.to_string()));
+                return;
+            }
+            Some(Ok(pattern)) => pattern,
+        };
+        find.count.set_tooltip_text(None);
+
+        let (buffer, _) = self.search_target(doc);
+        let matches = self.find.matches(&pattern, &buffer_text(&buffer));
+        let selected = selection(&buffer);
+        let mut current = None;
+        for (index, found) in matches.iter().enumerate() {
+            let (start, end) = (buffer.iter_at_offset(found.chars.0), buffer.iter_at_offset(found.chars.1));
+            if found.chars == selected {
+                buffer.apply_tag_by_name(CURRENT_TAG, &start, &end);
+                current = Some(index);
+            } else {
+                buffer.apply_tag_by_name(MATCH_TAG, &start, &end);
+            }
+        }
+        find.count.set_text(&match (current, matches.len()) {
+            (_, 0) => "no matches".to_string(),
+            (Some(index), total) => format!("{} of {}", index + 1, total),
+            (None, 1) => "1 match".to_string(),
+            (None, total) => format!("{} matches", total),
+        });
+    }
+
+    // select the next (or previous) match after the selection, going round at the end;
+    // `from_start` counts a match at the selection itself, for searching as you type
+    fn find_match(&self, forward: bool, from_start: bool) {
+        let doc = self.doc();
+        if !self.find.revealer.reveals_child() {
+            self.show_find(false);
+        }
+        let Some(Ok(pattern)) = self.find.pattern() else {
+            self.update_search(&doc);
+            return;
+        };
+        let (buffer, view) = self.search_target(&doc);
+        let matches = self.find.matches(&pattern, &buffer_text(&buffer));
+        let (start, end) = selection(&buffer);
+        let found = if forward {
+            let after = |found: &&Match| if from_start || start == end { found.chars.0 >= start } else { found.chars.0 > start };
+            matches.iter().find(after).or(matches.first())
+        } else {
+            matches.iter().rev().find(|found| found.chars.0 < start).or(matches.last())
+        };
+        if let Some(found) = found {
+            buffer.select_range(&buffer.iter_at_offset(found.chars.0), &buffer.iter_at_offset(found.chars.1));
+            view.scroll_to_mark(&buffer.get_insert(), 0.1, false, 0.0, 0.0);
+        }
+        self.update_search(&doc);
+    }
+
+    // replace the selected match and move on to the next one
+    fn replace_match(&self) {
+        let doc = self.doc();
+        let Some(Ok(pattern)) = self.find.pattern() else {
+            return;
+        };
+        if self.find.in_html.is_active() {
+            return;
+        }
+        let buffer = &doc.md_buffer;
+        let text = buffer_text(buffer);
+        let selected = selection(buffer);
+        let matches = self.find.matches(&pattern, &text);
+        let Some(found) = matches.iter().find(|found| found.chars == selected) else {
+            // nothing picked yet, pick the next one first
+            self.find_match(true, true);
+            return;
+        };
+        let replacement = self.find.replacement_for(&pattern, &text, found);
+        buffer.begin_user_action();
+        let (mut start, mut end) = (buffer.iter_at_offset(found.chars.0), buffer.iter_at_offset(found.chars.1));
+        buffer.delete(&mut start, &mut end);
+        buffer.insert(&mut start, &replacement);
+        buffer.end_user_action();
+        buffer.place_cursor(&start);
+        self.find_match(true, true);
+    }
+
+    // one undo step for the lot
+    fn replace_all(&self) {
+        let doc = self.doc();
+        let Some(Ok(pattern)) = self.find.pattern() else {
+            return;
+        };
+        if self.find.in_html.is_active() {
+            return;
+        }
+        let buffer = &doc.md_buffer;
+        let text = buffer_text(buffer);
+        let matches = self.find.matches(&pattern, &text);
+        if matches.is_empty() {
+            return;
+        }
+        buffer.begin_user_action();
+        // from the end, so the offsets of the ones still to do hold
+        for found in matches.iter().rev() {
+            let replacement = self.find.replacement_for(&pattern, &text, found);
+            let (mut start, mut end) = (buffer.iter_at_offset(found.chars.0), buffer.iter_at_offset(found.chars.1));
+            buffer.delete(&mut start, &mut end);
+            buffer.insert(&mut start, &replacement);
+        }
+        buffer.end_user_action();
+        self.update_search(&doc);
+        self.find.count.set_text(&format!("replaced {}", matches.len()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(pattern: &str, text: &str, whole_word: bool) -> Vec<(i32, i32)> {
+        let pattern = Regex::new(pattern).unwrap();
+        find_all(&pattern, text, whole_word).into_iter().map(|found| found.chars).collect()
+    }
+
+    #[test]
+    fn offsets_count_characters() {
+        let pattern = Regex::new("llo").unwrap();
+        let matches = find_all(&pattern, "héllo wörld héllo", false);
+        assert_eq!(matches.iter().map(|found| found.chars).collect::<Vec<_>>(), vec![(2, 5), (14, 17)]);
+        assert_eq!(matches.iter().map(|found| found.bytes).collect::<Vec<_>>(), vec![(3, 6), (17, 20)]);
+    }
+
+    #[test]
+    fn empty_matches_are_skipped() {
+        assert_eq!(spans("x*", "axxb", false), vec![(1, 3)]);
+    }
+
+    #[test]
+    fn whole_words_only_check_word_edges() {
+        assert_eq!(spans("cat", "cat concat cats cat_", true), vec![(0, 3)]);
+        assert_eq!(spans(r"C\+\+", "C++, xC++ C++11", true), vec![(0, 3), (10, 13)]);
+        assert_eq!(spans("#tag", "a#tag #tags #tag.", true), vec![(1, 5), (12, 16)]);
+        assert_eq!(spans("-->", "a-->b", true), vec![(1, 4)]);
+    }
+}
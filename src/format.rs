@@ -0,0 +1,242 @@
+use gtk4::prelude::*;
+use gtk4::gio::SimpleAction;
+use gtk4::{Box as GtkBox, Button, Label, Orientation, TextBuffer};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::rc::Rc;
+
+use crate::MarkdownEditor;
+
+// action, toolbar label (pango markup), tooltip
+const TOOLBAR_BUTTONS: [(&str, &str, &str); 5] = [
+    ("win.format-bold", "<b>B</b>", "Bold (Ctrl+B)"),
+    ("win.format-italic", "<i>I</i>", "Italic (Ctrl+I)"),
+    ("win.format-code", "<tt>`</tt>", "Inline code (Ctrl+`)"),
+    ("win.format-link", "<tt>[ ]</tt>", "Link (Ctrl+K)"),
+    ("win.cycle-heading", "<b>H</b>", "Heading level (Ctrl+1 to Ctrl+6, Ctrl+0 for none)"),
+];
+
+/// Buttons for the formatting actions, for the corner of the HTML pane.
+pub fn format_toolbar() -> GtkBox {
+    let toolbar = GtkBox::new(Orientation::Horizontal, 0);
+    toolbar.set_widget_name("format_toolbar");
+    for (action, markup, tooltip) in TOOLBAR_BUTTONS {
+        let label = Label::new(None);
+        label.set_markup(markup);
+        let button = Button::new();
+        button.set_child(Some(&label));
+        button.set_tooltip_text(Some(tooltip));
+        button.set_focus_on_click(false);
+        button.set_action_name(Some(action));
+        toolbar.append(&button);
+    }
+    toolbar
+}
+
+impl MarkdownEditor {
+    /// Bold, italic, code, link and heading actions on the markdown selection, with their shortcuts.
+    pub(crate) fn connect_format_actions(self: &Rc<Self>) {
+        let wraps = [("format-bold", "**"), ("format-italic", "*"), ("format-code", "`")];
+        for (name, marker) in wraps {
+            let action = SimpleAction::new(name, None);
+            let editor = self.clone();
+            action.connect_activate(move |_, _| {
+                let doc = editor.doc();
+                toggle_wrap(&doc.md_buffer, marker);
+                doc.text_view.grab_focus();
+            });
+            self.window.add_action(&action);
+        }
+
+        let link = SimpleAction::new("format-link", None);
+        let editor = self.clone();
+        link.connect_activate(move |_, _| {
+            let doc = editor.doc();
+            toggle_link(&doc.md_buffer);
+            doc.text_view.grab_focus();
+        });
+        self.window.add_action(&link);
+
+        let heading = SimpleAction::new("heading", Some(glib::VariantTy::INT32));
+        let editor = self.clone();
+        heading.connect_activate(move |_, level| {
+            let level = level.and_then(|level| level.get::<i32>()).unwrap_or(0);
+            let doc = editor.doc();
+            set_heading(&doc.md_buffer, |current| if current == level { 0 } else { level });
+            doc.text_view.grab_focus();
+        });
+        self.window.add_action(&heading);
+
+        let cycle_heading = SimpleAction::new("cycle-heading", None);
+        let editor = self.clone();
+        cycle_heading.connect_activate(move |_, _| {
+            let doc = editor.doc();
+            set_heading(&doc.md_buffer, |current| (current + 1) % 7);
+            doc.text_view.grab_focus();
+        });
+        self.window.add_action(&cycle_heading);
+
+        if let Some(app) = self.window.application() {
+            app.set_accels_for_action("win.format-bold", &["<Control>b"]);
+            app.set_accels_for_action("win.format-italic", &["<Control>i"]);
+            app.set_accels_for_action("win.format-code", &["<Control>grave"]);
+            app.set_accels_for_action("win.format-link", &["<Control>k"]);
+            for level in 0..=6 {
+                app.set_accels_for_action(&format!("win.heading({})", level), &[&format!("<Control>{}", level)]);
+            }
+        }
+    }
+}
+
+// the selection as character offsets, or the cursor twice
+fn selection(buffer: &TextBuffer) -> (i32, i32) {
+    match buffer.selection_bounds() {
+        Some((start, end)) => (start.offset(), end.offset()),
+        None => {
+            let cursor = buffer.iter_at_mark(&buffer.get_insert()).offset();
+            (cursor, cursor)
+        }
+    }
+}
+
+fn text_between(buffer: &TextBuffer, start: i32, end: i32) -> String {
+    buffer.text(&buffer.iter_at_offset(start), &buffer.iter_at_offset(end), false).to_string()
+}
+
+fn select(buffer: &TextBuffer, start: i32, end: i32) {
+    buffer.select_range(&buffer.iter_at_offset(start), &buffer.iter_at_offset(end));
+}
+
+fn delete(buffer: &TextBuffer, start: i32, end: i32) {
+    buffer.delete(&mut buffer.iter_at_offset(start), &mut buffer.iter_at_offset(end));
+}
+
+fn insert(buffer: &TextBuffer, at: i32, text: &str) {
+    buffer.insert(&mut buffer.iter_at_offset(at), text);
+}
+
+// how many of `marker`'s character run from `text` in the given direction
+fn run_length(text: &str, marker: &str, from_end: bool) -> usize {
+    let c = marker.chars().next().unwrap_or('*');
+    if from_end {
+        text.chars().rev().take_while(|&ch| ch == c).count()
+    } else {
+        text.chars().take_while(|&ch| ch == c).count()
+    }
+}
+
+// a run of `*` can be italic, bold or both (`***`), which counts as either
+fn is_marker_run(run: usize, marker: &str) -> bool {
+    let len = marker.chars().count();
+    run == len || (marker.starts_with('*') && run == 3)
+}
+
+/// Wrap the selection in `marker`, or unwrap it if it's already wrapped.
+fn toggle_wrap(buffer: &TextBuffer, marker: &str) {
+    let (start, end) = selection(buffer);
+    let len = marker.chars().count() as i32;
+    let selected = text_between(buffer, start, end);
+    let before = text_between(buffer, (start - 3).max(0), start);
+    let after = text_between(buffer, end, end + 3);
+
+    buffer.begin_user_action();
+    let inside = selected.chars().count() as i32 >= 2 * len
+        && is_marker_run(run_length(&selected, marker, false), marker)
+        && is_marker_run(run_length(&selected, marker, true), marker);
+    let around = is_marker_run(run_length(&before, marker, true), marker)
+        && is_marker_run(run_length(&after, marker, false), marker);
+    if inside {
+        // `**text**` selected
+        delete(buffer, end - len, end);
+        delete(buffer, start, start + len);
+        select(buffer, start, end - 2 * len);
+    } else if around {
+        // `text` selected inside `**text**`
+        delete(buffer, end, end + len);
+        delete(buffer, start - len, start);
+        select(buffer, start - len, end - len);
+    } else {
+        insert(buffer, end, marker);
+        insert(buffer, start, marker);
+        select(buffer, start + len, end + len);
+    }
+    buffer.end_user_action();
+}
+
+/// Turn the selection into a link, or back into plain text if it already is one.
+///
+/// A selected URL becomes the link's target with the cursor left in the text,
+/// anything else becomes the text with the cursor left in the target.
+fn toggle_link(buffer: &TextBuffer) {
+    lazy_static! {
+        static ref LINK_REGEX: Regex = Regex::new(r"^\[(.*)\]\((.*)\)$").unwrap();
+        static ref URL_REGEX: Regex = Regex::new(r"^(\w+://|www\.|mailto:)\S+$").unwrap();
+    }
+    let (start, end) = selection(buffer);
+    let selected = text_between(buffer, start, end);
+
+    buffer.begin_user_action();
+    delete(buffer, start, end);
+    if let Some(caps) = LINK_REGEX.captures(&selected) {
+        let text = &caps[1];
+        insert(buffer, start, text);
+        select(buffer, start, start + text.chars().count() as i32);
+    } else if URL_REGEX.is_match(selected.trim()) {
+        insert(buffer, start, &format!("[]({})", selected.trim()));
+        select(buffer, start + 1, start + 1);
+    } else {
+        insert(buffer, start, &format!("[{}]()", selected));
+        let target = start + selected.chars().count() as i32 + 3;
+        select(buffer, target, target);
+    }
+    buffer.end_user_action();
+}
+
+/// Give every selected line the heading level `level` picks from its current one (0 for none).
+fn set_heading(buffer: &TextBuffer, level: impl Fn(i32) -> i32) {
+    lazy_static! {
+        static ref HEADING_REGEX: Regex = Regex::new(r"^(#{1,6})\s+").unwrap();
+    }
+    let (start, end) = selection(buffer);
+    let first = buffer.iter_at_offset(start).line();
+    let last = buffer.iter_at_offset(end).line();
+
+    buffer.begin_user_action();
+    for line in first..=last {
+        let Some(line_start) = buffer.iter_at_line(line) else {
+            continue;
+        };
+        let mut line_end = line_start;
+        if !line_end.ends_line() {
+            line_end.forward_to_line_end();
+        }
+        let text = buffer.text(&line_start, &line_end, false);
+        let (prefix_len, current) = match HEADING_REGEX.captures(&text) {
+            Some(caps) => (caps[0].chars().count() as i32, caps[1].len() as i32),
+            None => (0, 0),
+        };
+        let new_level = level(current);
+        let offset = line_start.offset();
+        delete(buffer, offset, offset + prefix_len);
+        if new_level > 0 {
+            insert(buffer, offset, &format!("{} ", "#".repeat(new_level as usize)));
+        }
+    }
+    buffer.end_user_action();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marker_runs() {
+        assert_eq!(run_length("**bold**", "**", false), 2);
+        assert_eq!(run_length("*both***", "*", true), 3);
+        assert_eq!(run_length("plain", "`", false), 0);
+        assert!(is_marker_run(2, "**"));
+        assert!(is_marker_run(3, "*"));
+        assert!(!is_marker_run(3, "~~"));
+        assert!(!is_marker_run(1, "**"));
+    }
+}
@@ -0,0 +1,361 @@
+use gtk4::prelude::*;
+use gtk4::{TextBuffer, TextIter, TextTag};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::cell::Cell;
+use std::rc::Rc;
+
+// a highlighting palette entry: tag name, light colour, dark colour
+type Color = (&'static str, &'static str, &'static str);
+// a tag to apply to a line, by byte range
+type Span = (&'static str, usize, usize);
+// the marker of the multi-line construct a line starts or ends inside, if any
+type Inside = Option<&'static str>;
+
+/// How to colour one kind of buffer, a line at a time.
+struct Syntax {
+    colors: &'static [Color],
+    // set on the newline of every line that ends inside something spanning lines
+    // (a ``` block, an html comment or tag), one per kind of thing, so a line's
+    // neighbours can be retagged without rescanning from the top
+    markers: &'static [&'static str],
+    // spans for a line given the marker of what it starts inside, and what it ends inside
+    line_spans: fn(&str, Inside) -> (Vec<Span>, Inside),
+}
+
+const IN_CODE: &str = "md-in-code";
+const IN_COMMENT: &str = "html-in-comment";
+const IN_TAG: &str = "html-in-tag";
+
+const MARKDOWN: Syntax = Syntax {
+    colors: &[
+        ("md-header", "#8b4513", "#00ffff"),
+        ("md-strong", "#3e2f22", "#ffffff"),
+        ("md-emphasis", "#7a6550", "#7fff7f"),
+        ("md-code", "#a0522d", "#ffff00"),
+        ("md-fence", "#b09a7a", "#008800"),
+        ("md-code-block", "#6b4f33", "#ccff99"),
+        ("md-link", "#2a6f97", "#ff00ff"),
+        ("md-list-marker", "#b5651d", "#00ffff"),
+        ("md-table-pipe", "#b09a7a", "#008800"),
+    ],
+    markers: &[IN_CODE],
+    line_spans: markdown_spans,
+};
+
+const HTML: Syntax = Syntax {
+    colors: &[
+        ("html-tag", "#8b4513", "#00ffff"),
+        ("html-attr-name", "#b5651d", "#ffff00"),
+        ("html-attr-value", "#2a6f97", "#ff00ff"),
+        ("html-entity", "#a0522d", "#ff8800"),
+        ("html-comment", "#a08f7a", "#008800"),
+    ],
+    markers: &[IN_COMMENT, IN_TAG],
+    line_spans: html_spans,
+};
+
+/// Keep `buffer` tagged with markdown syntax colours as it's edited.
+///
+/// Only the lines an edit touched get retagged, plus whatever follows when a
+/// fence opened or closed.
+pub fn highlight_markdown(buffer: &TextBuffer) {
+    let table = buffer.tag_table();
+    table.add(&TextTag::builder().name("md-header").weight(700).build());
+    table.add(&TextTag::builder().name("md-strong").weight(700).build());
+    table.add(&TextTag::builder().name("md-emphasis").style(pango::Style::Italic).build());
+    table.add(&TextTag::builder()
+        .name("md-code")
+        .family("monospace")
+        .background("rgba(127, 127, 127, 0.2)")
+        .build());
+    table.add(&TextTag::builder().name("md-fence").family("monospace").build());
+    table.add(&TextTag::builder().name("md-code-block").family("monospace").build());
+    table.add(&TextTag::builder().name("md-link").underline(pango::Underline::Single).build());
+    table.add(&TextTag::builder().name("md-list-marker").weight(700).build());
+    table.add(&TextTag::builder().name("md-table-pipe").build());
+    highlight(buffer, &MARKDOWN);
+}
+
+/// Keep `buffer` tagged with html syntax colours: tags, attributes, entities and comments.
+pub fn highlight_html(buffer: &TextBuffer) {
+    let table = buffer.tag_table();
+    for (name, _, _) in HTML.colors {
+        table.add(&TextTag::builder().name(*name).build());
+    }
+    if let Some(comment) = table.lookup("html-comment") {
+        comment.set_style(pango::Style::Italic);
+    }
+    highlight(buffer, &HTML);
+}
+
+/// Switch every highlighting colour in `buffer` to the light or dark palette.
+pub fn set_dark(buffer: &TextBuffer, dark: bool) {
+    let table = buffer.tag_table();
+    for (name, light_color, dark_color) in MARKDOWN.colors.iter().chain(HTML.colors) {
+        if let Some(tag) = table.lookup(name) {
+            tag.set_foreground(Some(if dark { dark_color } else { light_color }));
+        }
+    }
+}
+
+fn highlight(buffer: &TextBuffer, syntax: &'static Syntax) {
+    for marker in syntax.markers {
+        buffer.tag_table().add(&TextTag::builder().name(*marker).build());
+    }
+    set_dark(buffer, false);
+
+    // lines to retag once the edit has landed, in post-edit line numbers
+    let pending = Rc::new(Cell::new(None::<(i32, i32)>));
+
+    let pending_insert = pending.clone();
+    buffer.connect_insert_text(move |_, location, text| {
+        let first = location.line();
+        let last = first + text.matches('\n').count() as i32;
+        pending_insert.set(Some(merge(pending_insert.get(), (first, last))));
+    });
+    let pending_delete = pending.clone();
+    buffer.connect_delete_range(move |_, start, _| {
+        let line = start.line();
+        pending_delete.set(Some(merge(pending_delete.get(), (line, line))));
+    });
+    buffer.connect_changed(move |buffer| {
+        if let Some((first, last)) = pending.take() {
+            retag_lines(buffer, syntax, first, last);
+        }
+    });
+}
+
+fn merge(range: Option<(i32, i32)>, (first, last): (i32, i32)) -> (i32, i32) {
+    match range {
+        Some((a, b)) => (a.min(first), b.max(last)),
+        None => (first, last),
+    }
+}
+
+fn retag_lines(buffer: &TextBuffer, syntax: &Syntax, first: i32, last: i32) {
+    let mut inside = if first > 0 { ends_inside(buffer, syntax, first - 1) } else { None };
+    let mut line = first;
+    while line < buffer.line_count() {
+        let was_inside = ends_inside(buffer, syntax, line);
+        inside = retag_line(buffer, syntax, line, inside);
+        // past the edit, stop once a line leaves things as they were
+        if line >= last && inside == was_inside {
+            break;
+        }
+        line += 1;
+    }
+}
+
+fn line_bounds(buffer: &TextBuffer, line: i32) -> Option<(TextIter, TextIter)> {
+    let start = buffer.iter_at_line(line)?;
+    let mut end = start;
+    if !end.ends_line() {
+        end.forward_to_line_end();
+    }
+    Some((start, end))
+}
+
+// the marker of whatever the line ends inside
+fn ends_inside(buffer: &TextBuffer, syntax: &Syntax, line: i32) -> Inside {
+    let (_, end) = line_bounds(buffer, line)?;
+    let table = buffer.tag_table();
+    syntax.markers.iter().copied().find(|marker| table.lookup(marker).is_some_and(|tag| end.has_tag(&tag)))
+}
+
+// tag one line given what multi-line construct it starts inside, returns what it ends inside
+fn retag_line(buffer: &TextBuffer, syntax: &Syntax, line: i32, inside: Inside) -> Inside {
+    let Some((start, end)) = line_bounds(buffer, line) else {
+        return inside;
+    };
+    let mut newline = end;
+    newline.forward_char();
+    for (name, _, _) in syntax.colors {
+        buffer.remove_tag_by_name(name, &start, &newline);
+    }
+    for marker in syntax.markers {
+        buffer.remove_tag_by_name(marker, &start, &newline);
+    }
+
+    let text = buffer.text(&start, &end, false);
+    let (spans, inside_after) = (syntax.line_spans)(&text, inside);
+    for (name, from, to) in spans {
+        let tag_start = buffer.iter_at_offset(start.offset() + text[..from].chars().count() as i32);
+        let tag_end = buffer.iter_at_offset(start.offset() + text[..to].chars().count() as i32);
+        buffer.apply_tag_by_name(name, &tag_start, &tag_end);
+    }
+    if let Some(marker) = inside_after {
+        buffer.apply_tag_by_name(marker, &end, &newline);
+    }
+    inside_after
+}
+
+fn markdown_spans(line: &str, inside: Inside) -> (Vec<Span>, Inside) {
+    let in_code = inside == Some(IN_CODE);
+    // same test the converter uses to open and close code blocks
+    if line.starts_with("```") {
+        (vec![("md-fence", 0, line.len())], (!in_code).then_some(IN_CODE))
+    } else if in_code {
+        (vec![("md-code-block", 0, line.len())], Some(IN_CODE))
+    } else {
+        (inline_spans(line), None)
+    }
+}
+
+// (tag, start byte, end byte) for everything worth colouring on a line outside code blocks
+fn inline_spans(line: &str) -> Vec<Span> {
+    lazy_static! {
+        static ref HEADER_REGEX: Regex = Regex::new(r"^#{1,6}\s").unwrap();
+        static ref LIST_MARKER_REGEX: Regex = Regex::new(r"^\s*(-) ").unwrap();
+        static ref CODE_REGEX: Regex = Regex::new(r"`[^`]+`").unwrap();
+        static ref LINK_REGEX: Regex = Regex::new(r"!?\[[^\]]*\]\([^)]*\)").unwrap();
+        static ref STRONG_REGEX: Regex = Regex::new(r"\*\*.+?\*\*").unwrap();
+        static ref EMPHASIS_REGEX: Regex = Regex::new(r"\*[^*]+?\*").unwrap();
+    }
+
+    let mut spans = Vec::new();
+    if HEADER_REGEX.is_match(line) {
+        spans.push(("md-header", 0, line.len()));
+    }
+    if let Some(marker) = LIST_MARKER_REGEX.captures(line).and_then(|caps| caps.get(1)) {
+        spans.push(("md-list-marker", marker.start(), marker.end()));
+    }
+    if line.trim_start().starts_with('|') {
+        spans.extend(line.match_indices('|').map(|(i, _)| ("md-table-pipe", i, i + 1)));
+    }
+
+    // like the converter, earlier patterns claim their text before later ones see it
+    let mut masked = line.to_string();
+    for (name, regex) in [
+        ("md-code", &*CODE_REGEX),
+        ("md-link", &*LINK_REGEX),
+        ("md-strong", &*STRONG_REGEX),
+        ("md-emphasis", &*EMPHASIS_REGEX),
+    ] {
+        let found = regex.find_iter(&masked).map(|m| m.range()).collect::<Vec<_>>();
+        for range in found {
+            spans.push((name, range.start, range.end));
+            masked.replace_range(range.clone(), &" ".repeat(range.len()));
+        }
+    }
+    spans
+}
+
+fn html_spans(line: &str, inside: Inside) -> (Vec<Span>, Inside) {
+    lazy_static! {
+        static ref TOKEN_REGEX: Regex = Regex::new(r"<!--|<[!/]?[A-Za-z][^\s>/]*|&#?\w+;").unwrap();
+    }
+
+    let mut spans = Vec::new();
+    let mut comment_start = (inside == Some(IN_COMMENT)).then_some(0);
+    let mut in_tag = inside == Some(IN_TAG);
+    let mut pos = 0;
+    loop {
+        if let Some(from) = comment_start.take() {
+            match line[pos..].find("-->") {
+                Some(end) => {
+                    pos += end + 3;
+                    spans.push(("html-comment", from, pos));
+                }
+                None => {
+                    spans.push(("html-comment", from, line.len()));
+                    return (spans, Some(IN_COMMENT));
+                }
+            }
+        }
+        // a tag's attributes up to the closing `>`, which may be lines away
+        if in_tag {
+            if !attribute_spans(line, &mut pos, &mut spans) {
+                return (spans, Some(IN_TAG));
+            }
+            in_tag = false;
+        }
+        let Some(token) = TOKEN_REGEX.find(&line[pos..]) else {
+            break;
+        };
+        let (token_start, token_end) = (pos + token.start(), pos + token.end());
+        pos = token_end;
+        if token.as_str() == "<!--" {
+            comment_start = Some(token_start);
+        } else if token.as_str().starts_with('&') {
+            spans.push(("html-entity", token_start, token_end));
+        } else {
+            spans.push(("html-tag", token_start, token_end));
+            in_tag = true;
+        }
+    }
+    (spans, None)
+}
+
+// spans for the attributes from `pos` on, stepping `pos` past them; true once the tag closes
+fn attribute_spans(line: &str, pos: &mut usize, spans: &mut Vec<Span>) -> bool {
+    lazy_static! {
+        // anchored, so only what directly follows the tag name counts; `.` steps over stray quotes
+        static ref ATTRIBUTE_REGEX: Regex =
+            Regex::new(r#"^\s*(?:(/?>)|([^\s=/>"']+)(?:\s*=\s*("[^"]*"?|'[^']*'?|[^\s>"']+))?|.)"#).unwrap();
+    }
+    while let Some(caps) = ATTRIBUTE_REGEX.captures(&line[*pos..]) {
+        let offset = *pos;
+        *pos += caps.get(0).unwrap().end();
+        if let Some(close) = caps.get(1) {
+            spans.push(("html-tag", offset + close.start(), offset + close.end()));
+            return true;
+        }
+        if let Some(name) = caps.get(2) {
+            spans.push(("html-attr-name", offset + name.start(), offset + name.end()));
+        }
+        if let Some(value) = caps.get(3) {
+            spans.push(("html-attr-value", offset + value.start(), offset + value.end()));
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tagged<'a>(line: &'a str, spans: &[Span], name: &str) -> Vec<&'a str> {
+        spans.iter().filter(|span| span.0 == name).map(|&(_, from, to)| &line[from..to]).collect()
+    }
+
+    #[test]
+    fn attributes_only_inside_tags() {
+        let line = r#"<a href="x.html">link</a> and then key="value""#;
+        let (spans, inside) = html_spans(line, None);
+        assert_eq!(tagged(line, &spans, "html-attr-name"), vec!["href"]);
+        assert_eq!(tagged(line, &spans, "html-attr-value"), vec![r#""x.html""#]);
+        assert_eq!(inside, None);
+    }
+
+    #[test]
+    fn tags_carry_over_lines() {
+        let first = r#"<img src="a.png""#;
+        let (spans, inside) = html_spans(first, None);
+        assert_eq!(tagged(first, &spans, "html-attr-name"), vec!["src"]);
+        assert_eq!(inside, Some(IN_TAG));
+
+        let second = r#"  alt="A">after key="value""#;
+        let (spans, inside) = html_spans(second, Some(IN_TAG));
+        assert_eq!(tagged(second, &spans, "html-attr-name"), vec!["alt"]);
+        assert_eq!(tagged(second, &spans, "html-tag"), vec![">"]);
+        assert_eq!(inside, None);
+    }
+
+    #[test]
+    fn comments_carry_over_lines() {
+        let (spans, inside) = html_spans("<p>a <!-- note", None);
+        assert_eq!(inside, Some(IN_COMMENT));
+        assert_eq!(spans.last(), Some(&("html-comment", 5, 14)));
+        let (spans, inside) = html_spans("end --> <b>", Some(IN_COMMENT));
+        assert_eq!(spans[0], ("html-comment", 0, 7));
+        assert_eq!(inside, None);
+    }
+
+    #[test]
+    fn fences_toggle_code() {
+        assert_eq!(markdown_spans("```rust", None).1, Some(IN_CODE));
+        assert_eq!(markdown_spans("# not a heading", Some(IN_CODE)).0, vec![("md-code-block", 0, 15)]);
+        assert_eq!(markdown_spans("```", Some(IN_CODE)).1, None);
+    }
+}
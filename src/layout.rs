@@ -0,0 +1,206 @@
+use gtk4::prelude::*;
+use gtk4::gio::{Menu, SimpleAction};
+use gtk4::Orientation;
+use std::rc::Rc;
+
+use crate::MarkdownEditor;
+
+/// How the two panes are arranged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// markdown on the left, preview on the right
+    Horizontal,
+    /// markdown above the preview
+    Vertical,
+    /// just the markdown
+    Editor,
+}
+
+impl Layout {
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Horizontal => "horizontal",
+            Layout::Vertical => "vertical",
+            Layout::Editor => "editor",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "horizontal" => Some(Layout::Horizontal),
+            "vertical" => Some(Layout::Vertical),
+            "editor" => Some(Layout::Editor),
+            _ => None,
+        }
+    }
+
+    // Ctrl+\ steps through these in order
+    fn next(self) -> Self {
+        match self {
+            Layout::Horizontal => Layout::Vertical,
+            Layout::Vertical => Layout::Editor,
+            Layout::Editor => Layout::Horizontal,
+        }
+    }
+}
+
+/// A window size or state asked for on the command line, over the remembered one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowSize {
+    Size(i32, i32),
+    Maximized,
+    Fullscreen,
+}
+
+impl WindowSize {
+    /// `WIDTHxHEIGHT`, `maximized` or `fullscreen`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "maximized" => Some(WindowSize::Maximized),
+            "fullscreen" => Some(WindowSize::Fullscreen),
+            size => {
+                let (width, height) = size.split_once('x')?;
+                let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+                (width > 0 && height > 0).then_some(WindowSize::Size(width, height))
+            }
+        }
+    }
+}
+
+impl MarkdownEditor {
+    /// Put the window back how it was last closed (or how the command line asks),
+    /// remember it again on close, and switch layouts from the right-click menu or Ctrl+\.
+    pub(crate) fn connect_window_state(self: &Rc<Self>, size: Option<WindowSize>, layout: Option<Layout>) {
+        {
+            let mut settings = self.settings.borrow_mut();
+            let state = &mut settings.window;
+            match size {
+                Some(WindowSize::Size(width, height)) => {
+                    state.width = width;
+                    state.height = height;
+                    state.maximized = false;
+                    state.fullscreen = false;
+                }
+                Some(WindowSize::Maximized) => {
+                    state.maximized = true;
+                    state.fullscreen = false;
+                }
+                Some(WindowSize::Fullscreen) => state.fullscreen = true,
+                None => {}
+            }
+            if let Some(layout) = layout {
+                state.layout = layout;
+            }
+
+            self.window.set_default_size(state.width, state.height);
+            if state.maximized {
+                self.window.maximize();
+            }
+            if state.fullscreen {
+                self.window.fullscreen();
+            }
+            if let Some(position) = state.pane_position {
+                self.paned.set_position(position);
+            }
+        }
+        let initial = self.settings.borrow().window.layout;
+        self.show_layout(initial);
+
+        let layout = SimpleAction::new_stateful("layout", Some(glib::VariantTy::STRING), &initial.name().to_variant());
+        let editor = self.clone();
+        layout.connect_activate(move |action, name| {
+            let Some(layout) = name.and_then(|name| name.str()).and_then(Layout::from_name) else {
+                return;
+            };
+            action.set_state(&layout.name().to_variant());
+            editor.settings.borrow_mut().window.layout = layout;
+            editor.show_layout(layout);
+            // the old position means something else across the other way
+            let size = match layout {
+                Layout::Vertical => editor.paned.height(),
+                _ => editor.paned.width(),
+            };
+            editor.paned.set_position(size / 2);
+            editor.doc().text_view.grab_focus();
+        });
+        self.window.add_action(&layout);
+
+        let cycle_layout = SimpleAction::new("cycle-layout", None);
+        let editor = self.clone();
+        cycle_layout.connect_activate(move |_, _| {
+            let next = editor.settings.borrow().window.layout.next();
+            if let Some(action) = editor.window.lookup_action("layout") {
+                action.activate(Some(&next.name().to_variant()));
+            }
+        });
+        self.window.add_action(&cycle_layout);
+
+        let fullscreen = SimpleAction::new("fullscreen", None);
+        let window = self.window.clone();
+        fullscreen.connect_activate(move |_, _| window.set_fullscreened(!window.is_fullscreen()));
+        self.window.add_action(&fullscreen);
+
+        if let Some(app) = self.window.application() {
+            app.set_accels_for_action("win.cycle-layout", &["<Control>backslash"]);
+            app.set_accels_for_action("win.fullscreen", &["F11"]);
+        }
+
+        // next to the export actions in the markdown pane's right-click menu
+        let layouts = Menu::new();
+        layouts.append(Some("Side by Side"), Some("win.layout::horizontal"));
+        layouts.append(Some("Stacked"), Some("win.layout::vertical"));
+        layouts.append(Some("Hide Preview"), Some("win.layout::editor"));
+        self.context_menu.append_section(None, &layouts);
+
+        // runs once the unsaved-changes check has let the window go
+        let editor = self.clone();
+        self.window.connect_close_request(move |window| {
+            let mut settings = editor.settings.borrow_mut();
+            // gtk keeps the unmaximized size here, which is the one worth restoring
+            let (width, height) = window.default_size();
+            settings.window.width = width;
+            settings.window.height = height;
+            settings.window.maximized = window.is_maximized();
+            settings.window.fullscreen = window.is_fullscreen();
+            settings.window.pane_position = Some(editor.paned.position());
+            settings.window.sidebar_position = Some(editor.sidebar.position());
+            if let Err(err) = settings.save() {
+                eprintln!("entermd: couldn't save settings: {}", err);
+            }
+            glib::Propagation::Proceed
+        });
+    }
+
+    fn show_layout(&self, layout: Layout) {
+        let orientation = match layout {
+            Layout::Vertical => Orientation::Vertical,
+            _ => Orientation::Horizontal,
+        };
+        self.paned.set_orientation(orientation);
+        if let Some(preview) = self.paned.end_child() {
+            preview.set_visible(layout != Layout::Editor);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_sizes() {
+        assert_eq!(WindowSize::parse("1280x800"), Some(WindowSize::Size(1280, 800)));
+        assert_eq!(WindowSize::parse(" Maximized "), Some(WindowSize::Maximized));
+        assert_eq!(WindowSize::parse("fullscreen"), Some(WindowSize::Fullscreen));
+        assert_eq!(WindowSize::parse("0x600"), None);
+        assert_eq!(WindowSize::parse("800"), None);
+        assert_eq!(WindowSize::parse("wide"), None);
+    }
+
+    #[test]
+    fn layouts_cycle_back_round() {
+        assert_eq!(Layout::from_name("Editor"), Some(Layout::Editor));
+        assert_eq!(Layout::from_name("sideways"), None);
+        assert_eq!(Layout::Horizontal.next().next().next(), Layout::Horizontal);
+    }
+}
@@ -0,0 +1,161 @@
+use gtk4::prelude::*;
+use gtk4::gdk::{Key, ModifierType};
+use gtk4::{EventControllerKey, PropagationPhase, TextBuffer, TextIter};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use crate::document::Document;
+use crate::MarkdownEditor;
+
+// one level of list nesting, spaces since that's what the converter's nesting goes by
+const INDENT: &str = "  ";
+
+lazy_static! {
+    // indent, bullet or number and its `.`/`)`, the space after it, and an optional task box
+    static ref LIST_ITEM_REGEX: Regex = Regex::new(r"^(\s*)(?:([-*+])|(\d+)([.)]))(\s+)(\[[ xX]\]\s+)?").unwrap();
+}
+
+impl MarkdownEditor {
+    /// Enter continues a list and Tab/Shift+Tab nest list items, everything else types as usual.
+    pub(crate) fn connect_list_editing(&self, doc: &Document) {
+        let keys = EventControllerKey::new();
+        // before the text view gets to insert its newline or tab
+        keys.set_propagation_phase(PropagationPhase::Capture);
+        let buffer = doc.md_buffer.clone();
+        keys.connect_key_pressed(move |_, key, _, modifiers| {
+            if modifiers.intersects(ModifierType::CONTROL_MASK | ModifierType::ALT_MASK) {
+                return glib::Propagation::Proceed;
+            }
+            let shift = modifiers.contains(ModifierType::SHIFT_MASK);
+            let handled = match key {
+                Key::Return | Key::KP_Enter if !shift => continue_list(&buffer),
+                Key::Tab if !shift => indent_items(&buffer, true),
+                // shift+tab mostly arrives as ISO_Left_Tab
+                Key::Tab | Key::ISO_Left_Tab => indent_items(&buffer, false),
+                _ => false,
+            };
+            if handled {
+                glib::Propagation::Stop
+            } else {
+                glib::Propagation::Proceed
+            }
+        });
+        doc.text_view.add_controller(keys);
+    }
+}
+
+fn line_bounds(buffer: &TextBuffer, line: i32) -> Option<(TextIter, TextIter)> {
+    let start = buffer.iter_at_line(line)?;
+    let mut end = start;
+    if !end.ends_line() {
+        end.forward_to_line_end();
+    }
+    Some((start, end))
+}
+
+// the marker starting the next item after `item`: same bullet, the next number, an unticked box
+fn next_marker(item: &Captures) -> String {
+    let bullet = match (item.get(2), item.get(3), item.get(4)) {
+        (Some(bullet), _, _) => bullet.as_str().to_string(),
+        (None, Some(number), Some(delimiter)) => {
+            let next = number.as_str().parse::<u64>().map_or(1, |n| n.saturating_add(1));
+            format!("{}{}", next, delimiter.as_str())
+        }
+        _ => "-".to_string(),
+    };
+    let task = if item.get(6).is_some() { "[ ] " } else { "" };
+    format!("{}{}{}{}", &item[1], bullet, &item[5], task)
+}
+
+/// Start the next item when Enter is pressed in a list item, or end the list on an empty one.
+fn continue_list(buffer: &TextBuffer) -> bool {
+    if buffer.has_selection() {
+        return false;
+    }
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let Some((mut start, mut end)) = line_bounds(buffer, cursor.line()) else {
+        return false;
+    };
+    let line = buffer.text(&start, &end, false);
+    let Some(item) = LIST_ITEM_REGEX.captures(&line) else {
+        return false;
+    };
+    // with the cursor in the marker itself, Enter just breaks the line
+    let marker_end = line[..item[0].len()].chars().count() as i32;
+    if cursor.line_offset() < marker_end {
+        return false;
+    }
+
+    buffer.begin_user_action();
+    if line[item[0].len()..].trim().is_empty() {
+        // an empty item ends the list
+        buffer.delete(&mut start, &mut end);
+    } else {
+        buffer.insert_at_cursor(&format!("\n{}", next_marker(&item)));
+    }
+    buffer.end_user_action();
+    true
+}
+
+/// Nest (or un-nest) the list items in the selection by one level.
+///
+/// Returns false, leaving Tab to insert a tab, when the cursor isn't on a list item.
+fn indent_items(buffer: &TextBuffer, indent: bool) -> bool {
+    let (start, end) = buffer.selection_bounds().unwrap_or_else(|| {
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        (cursor, cursor)
+    });
+    let first = start.line();
+    // a selection ending at the start of a line doesn't include that line
+    let last = if end.line() > first && end.starts_line() { end.line() - 1 } else { end.line() };
+
+    let is_item = |line| {
+        line_bounds(buffer, line).is_some_and(|(start, end)| LIST_ITEM_REGEX.is_match(&buffer.text(&start, &end, false)))
+    };
+    if !is_item(first) {
+        return false;
+    }
+
+    buffer.begin_user_action();
+    for line in first..=last {
+        if !is_item(line) {
+            continue;
+        }
+        let Some((mut line_start, line_end)) = line_bounds(buffer, line) else {
+            continue;
+        };
+        if indent {
+            buffer.insert(&mut line_start, INDENT);
+            continue;
+        }
+        let text = buffer.text(&line_start, &line_end, false);
+        let remove = if text.starts_with('\t') {
+            1
+        } else {
+            text.chars().take(INDENT.len()).take_while(|&c| c == ' ').count() as i32
+        };
+        let mut indent_end = line_start;
+        indent_end.forward_chars(remove);
+        buffer.delete(&mut line_start, &mut indent_end);
+    }
+    buffer.end_user_action();
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next(line: &str) -> String {
+        next_marker(&LIST_ITEM_REGEX.captures(line).unwrap())
+    }
+
+    #[test]
+    fn next_items_keep_the_marker() {
+        assert_eq!(next("- milk"), "- ");
+        assert_eq!(next("    * nested"), "    * ");
+        assert_eq!(next("9) ninth"), "10) ");
+        assert_eq!(next("1.  wide"), "2.  ");
+        assert_eq!(next("- [x] done"), "- [ ] ");
+        assert!(LIST_ITEM_REGEX.captures("-not a list").is_none());
+    }
+}
@@ -1,28 +1,105 @@
 use gtk4::prelude::*;
 use gtk4::{
-    Application, ApplicationWindow, Button, CssProvider, Image, Orientation, Overlay, Paned, 
-    ScrolledWindow, TextView, TextBuffer, WrapMode, Align, STYLE_PROVIDER_PRIORITY_APPLICATION,
+    Application, ApplicationWindow, Box as GtkBox, CssProvider, Expander, Image, Label, MenuButton, Notebook, Orientation,
+    Overlay, Paned, Stack, TextBuffer, ToggleButton, TreeStore, TreeView, Align,
+    STYLE_PROVIDER_PRIORITY_APPLICATION, STYLE_PROVIDER_PRIORITY_USER,
 };
-use glib::{self, timeout_add_local};
-use std::time::Duration;
-use std::cell::RefCell;
+use gtk4::gio::{self, FileMonitor, Menu, SimpleAction};
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use std::collections::HashSet;
-use rand::Rng;
+use std::time::Duration;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
 // import markdown.rs as a module
 mod markdown;
-use crate::markdown::markdown_to_html;
+mod template;
+mod site;
+mod watch;
+mod serve;
+mod files;
+mod preview;
+mod highlight;
+mod sourcemap;
+mod update;
+mod format;
+mod lists;
+mod settings;
+mod theme;
+mod layout;
+mod config;
+mod preferences;
+mod document;
+mod sidebar;
+mod outline;
+mod find;
+mod cli;
+#[cfg(test)]
+mod testing;
+use crate::markdown::{markdown_to_html, Options};
+use crate::template::{render_page, DEFAULT_ROOT, HTML_TEMPLATE};
+use crate::serve::{PreviewServer, LIVE_PAGE_PATH};
+use crate::cli::{Command, EditorArgs};
+use crate::settings::Settings;
+use crate::config::{Config, ConverterConfig};
+use crate::document::Document;
+use crate::find::FindBar;
 
 struct MarkdownEditor {
     window: ApplicationWindow,
-    md_buffer: TextBuffer,
-    html_buffer: TextBuffer,
-    update_pending: Rc<RefCell<bool>>,
-    html_template: String,
-    markdown_chars: HashSet<char>,
-    //is_dark_mode: Rc<RefCell<bool>>,
-    //css_provider: CssProvider,
+    // the project's files one side, the editor the other
+    sidebar: Paned,
+    project_view: TreeView,
+    project_store: TreeStore,
+    project_title: Label,
+    project_menu: MenuButton,
+    project_dir: RefCell<Option<PathBuf>>,
+    // one per folder in the project, so the tree notices files coming and going
+    project_monitors: RefCell<Vec<FileMonitor>>,
+    project_refresh_pending: Cell<bool>,
+    // the current document's headings, under the project files
+    outline_expander: Expander,
+    outline_view: TreeView,
+    outline_store: TreeStore,
+    find: FindBar,
+    // the tabs one side, the current tab's preview the other, turned around or closed by the layout
+    paned: Paned,
+    notebook: Notebook,
+    // every open document, see `doc()` for the current one
+    documents: RefCell<Vec<Rc<Document>>>,
+    // each document's preview, showing the current one's
+    previews: Stack,
+    // the markdown panes' right-click menu
+    context_menu: Menu,
+    // exported pages go here instead of next to their markdown
+    export_dir: RefCell<Option<PathBuf>>,
+    export_on_save: Rc<Cell<bool>>,
+    html_template: RefCell<String>,
+    options: Cell<Options>,
+    // how long typing has to pause before the preview catches up
+    update_delay: Cell<Duration>,
+    // whether previews show the rendered page rather than the HTML source
+    rendered: Cell<bool>,
+    scroll_sync: Cell<bool>,
+    preview_server: Option<PreviewServer>,
+    settings: Rc<RefCell<Settings>>,
+    // the current theme, reloaded when the theme or its file changes
+    css_provider: CssProvider,
+    // light, dark, system or a user theme
+    theme: RefCell<String>,
+    // whether the current theme is dark, for highlighting new documents to match
+    dark: Cell<bool>,
+    // fonts from entermd.toml, over whatever the theme picks
+    font_provider: CssProvider,
+    theme_button: MenuButton,
+    // problems loading a user theme, over the top of the HTML pane
+    theme_errors: Label,
+    themes_monitor: RefCell<Option<FileMonitor>>,
+    // tells us when the desktop switches between light and dark
+    settings_portal: RefCell<Option<gio::DBusProxy>>,
+    // the portal's last word on whether the desktop is dark, None without one
+    portal_dark: Cell<Option<bool>>,
 }
 
 const LIGHT_MODE_CSS:&str = "
@@ -30,7 +107,7 @@ window {
     background-color: #f0e6d2; /* Warm cream background */
 }
 
-textview#static_html { 
+textview#static_html, textview#rendered_preview { 
     font-family: 'Segoe UI', system-ui, -apple-system, sans-serif;
     font-size: 14px;
     background-color: #e8dcc3; /* Soft beige background */
@@ -47,7 +124,7 @@ textview#static_html:focus {
     border-color: #c0a080; /* Darker border on focus */
 }
 
-textview#static_html text {
+textview#static_html text, textview#rendered_preview text {
     background-color: transparent;
 }
 
@@ -72,7 +149,42 @@ textview#markdown_input text {
     background-color: transparent;
 }
 
-button#theme_toggle {
+notebook#documents > header, notebook#documents > stack {
+    background-color: transparent;
+    border: none;
+}
+
+notebook#documents > header tab {
+    color: #5c4b3a;
+    border-radius: 8px 8px 0 0;
+    padding: 4px 8px;
+}
+
+notebook#documents > header tab:checked {
+    background-color: #e8dcc3; /* Same beige as the page */
+}
+
+#project_sidebar, treeview#project_tree, treeview#outline {
+    background-color: #e8dcc3; /* Soft beige background */
+    color: #5c4b3a; /* Warm brown text */
+}
+
+#project_header {
+    padding: 4px 4px 4px 10px;
+    font-weight: bold;
+}
+
+treeview#project_tree:selected, treeview#outline:selected {
+    background-color: #c0a080;
+}
+
+#find_bar {
+    padding: 6px 10px;
+    background-color: #e8dcc3; /* Soft beige background */
+    border-top: 1px solid #d4c5a8; /* Light beige border */
+}
+
+#theme_toggle > button {
     background-color: #d4c5a8;
     border-radius: 50%;
     border: none;
@@ -81,7 +193,24 @@ button#theme_toggle {
     box-shadow: 0 2px 5px rgba(0,0,0,0.1);
 }
 
-button#theme_toggle:hover {
+#theme_toggle > button:hover {
+    background-color: #c0a080;
+}
+
+button#preview_toggle, button#sync_toggle, #format_toolbar button {
+    background-color: #d4c5a8;
+    color: #5c4b3a;
+    border-radius: 50%;
+    border: none;
+    min-width: 36px;
+    min-height: 36px;
+    padding: 8px;
+    margin: 8px 0px 8px 8px;
+    box-shadow: 0 2px 5px rgba(0,0,0,0.1);
+}
+
+button#preview_toggle:hover, button#preview_toggle:checked,
+button#sync_toggle:hover, button#sync_toggle:checked, #format_toolbar button:hover {
     background-color: #c0a080;
 }
 ";
@@ -91,7 +220,7 @@ window {
     background-color: #0c0c0c; /* D33p bl4ck b4ckgr0und */
 }
 
-textview#static_html { 
+textview#static_html, textview#rendered_preview { 
     font-family: 'Courier New', monospace;
     font-size: 14px;
     background-color: #000000; /* Pur3 bl4ck b4ckgr0und */
@@ -111,10 +240,14 @@ textview#static_html:focus {
     border: 1px solid #00ff00; /* Br1ght3r gr33n 0n f0cus */
 }
 
-textview#static_html text {
+textview#static_html text, textview#rendered_preview text {
     background-color: transparent;
 }
 
+textview#rendered_preview {
+    font-family: 'Segoe UI', system-ui, sans-serif; /* r34d4bl3 r3nd3r */
+}
+
 textview#markdown_input {
     font-family: 'Courier New', monospace;
     font-size: 14px;
@@ -146,7 +279,45 @@ textview#markdown_input text selection {
     color: #ffffff; /* Wh1t3 t3xt f0r s3l3ct3d t3xt */
 }
 
-button#theme_toggle {
+notebook#documents > header, notebook#documents > stack {
+    background-color: transparent;
+    border: none;
+}
+
+notebook#documents > header tab {
+    color: #00ff00; /* Gr33n t4bs */
+    border-radius: 0px;
+    padding: 4px 8px;
+}
+
+notebook#documents > header tab:checked {
+    background-color: #000000;
+    border: 1px solid #00ff00; /* N30n gr33n b0rd3r */
+    border-bottom: none;
+}
+
+#project_sidebar, treeview#project_tree, treeview#outline {
+    background-color: #000000;
+    color: #00ff00; /* Gr33n f1l3s */
+}
+
+#project_header {
+    padding: 4px 4px 4px 10px;
+    font-weight: bold;
+    border-bottom: 1px solid #00ff00;
+}
+
+treeview#project_tree:selected, treeview#outline:selected {
+    background-color: rgba(0, 255, 0, 0.3); /* Gr33n s3l3ct10n */
+}
+
+#find_bar {
+    padding: 6px 10px;
+    background-color: #000000;
+    border-top: 1px solid #00ff00;
+}
+
+#theme_toggle > button {
     background-color: #000000;
     border: 1px solid #00ff00;
     border-radius: 0px; /* Sh4rp 3dg3s f0r h4x0r l00k */
@@ -157,14 +328,32 @@ button#theme_toggle {
     box-shadow: 0 0 8px rgba(0, 255, 0, 0.5);
 }
 
-button#theme_toggle:hover {
+#theme_toggle > button:hover {
+    background-color: #003300;
+    box-shadow: 0 0 12px rgba(0, 255, 0, 0.8);
+}
+
+button#preview_toggle, button#sync_toggle, #format_toolbar button {
+    background-color: #000000;
+    color: #00ff00;
+    border: 1px solid #00ff00;
+    border-radius: 0px;
+    min-width: 36px;
+    min-height: 36px;
+    padding: 8px;
+    margin: 8px 0px 8px 8px;
+    box-shadow: 0 0 8px rgba(0, 255, 0, 0.5);
+}
+
+button#preview_toggle:hover, button#preview_toggle:checked,
+button#sync_toggle:hover, button#sync_toggle:checked, #format_toolbar button:hover {
     background-color: #003300;
     box-shadow: 0 0 12px rgba(0, 255, 0, 0.8);
 }
 ";
 
 const THEME_BUTTON_CSS:&str = "
-button#theme_toggle {
+#theme_toggle > button {
     background-color: #d4c5a8;
     border-radius: 50%;
     border: none;
@@ -176,17 +365,17 @@ button#theme_toggle {
     overflow: hidden; /* Ensure content doesn't overflow the circular shape */
 }
 
-button#theme_toggle:hover {
+#theme_toggle > button:hover {
     background-color: #c0a080;
 }
 
-button#theme_toggle image {
+#theme_toggle > button image {
     -gtk-icon-transform: none;
     color: #5c4b3a; /* Icon color for light mode */
     background-color: transparent; /* Ensure image background is transparent */
 }
 
-button#theme_toggle.dark {
+#theme_toggle.dark > button {
     background-color: #000000;
     border: 1px solid #00ff00;
     border-radius: 50%; /* Keep circular shape in dark mode */
@@ -198,16 +387,23 @@ button#theme_toggle.dark {
     overflow: hidden; /* Ensure content doesn't overflow the circular shape */
 }
 
-button#theme_toggle.dark:hover {
+#theme_toggle.dark > button:hover {
     background-color: #003300;
     box-shadow: 0 0 12px rgba(0, 255, 0, 0.8);
 }
 
-button#theme_toggle.dark image {
+#theme_toggle.dark > button image {
     -gtk-icon-transform: rotate(180deg); /* Rotate the icon in dark mode */
     color: #00ff00; /* Neon green icon for dark mode */
     background-color: transparent; /* Ensure image background is transparent */
 }
+
+label#theme_errors {
+    background-color: #b3261e;
+    color: #ffffff;
+    font-family: monospace;
+    padding: 8px 12px;
+}
 ";
 
 //  TODO:
@@ -215,102 +411,85 @@ button#theme_toggle.dark image {
 // - rewrite in x86 (?)
 //
 // - Create codeblocks on my website with markdown editor's output
-// - live browser updates?
-// - markdown + html syntax highlighting
 //
 //
 
 impl MarkdownEditor {
-    fn new(app: &Application) -> Self {
+    fn new(
+        app: &Application,
+        options: Options,
+        preview_server: Option<PreviewServer>,
+        export_dir: Option<PathBuf>,
+        export_on_save: bool,
+    ) -> Rc<Self> {
 
         let window = ApplicationWindow::new(app);
         window.set_title(Some("Markdown -> HTML *Attempt*"));
         window.set_default_size(800, 600);
-        window.fullscreen(); // force fullscreen during demo recording
-
-        // html template with proper indentation
-        let html_template = String::from(
-            "<!DOCTYPE html>\n\
-             <html lang=\"en\">\n\
-             <head>\n\
-             \t<meta charset=\"UTF-8\">\n\
-             \t<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">\n\
-             \t<meta name=\"description\" content=\"e-sayin website description\">\n\
-             \t<meta name=\"color-scheme\" content=\"light-only\">\n\
-             \t<link rel\"stylesheet\" href=\"../style.css\">\n\
-             </head>\n\
-             <body>\n\
-             \n\
-             {}\n\
-             </body>\n\
-             </html>"
-        );
 
-        // create split pane
+        let html_template = String::from(HTML_TEMPLATE);
+
+        // the project sidebar, then the split pane
+        let sidebar = Paned::new(Orientation::Horizontal);
+        window.set_child(Some(&sidebar));
+        let (project, project_view, project_title, project_menu) = sidebar::project_sidebar();
+        let (outline_expander, outline_view) = outline::outline_panel();
+        project.append(&outline_expander);
+        let outline_store = outline_view.model().and_downcast::<TreeStore>().expect("the outline has a store");
+        sidebar.set_start_child(Some(&project));
+        sidebar.set_resize_start_child(false);
+        sidebar.set_shrink_start_child(false);
+        sidebar.set_position(220);
+        let project_store = project_view.model().and_downcast::<TreeStore>().expect("the project tree has a store");
+
+        // create split pane, with the find bar under it
         let paned = Paned::new(Orientation::Horizontal);
-        window.set_child(Some(&paned));
-
-        // left side
-        let left_md_input = ScrolledWindow::new();
-        let text_view = TextView::new();
-        text_view.set_widget_name("markdown_input");
-        text_view.set_wrap_mode(WrapMode::Word); // Wrap at word boundaries
-        
-        // Enable paste handling
-        text_view.set_accepts_tab(true);
-        
-        let md_buffer = text_view.buffer();
-        left_md_input.set_child(Some(&text_view));
-        paned.set_start_child(Some(&left_md_input));
+        paned.set_vexpand(true);
+        let find = find::find_bar();
+        let editor_area = GtkBox::new(Orientation::Vertical, 0);
+        editor_area.append(&paned);
+        editor_area.append(&find.revealer);
+        sidebar.set_end_child(Some(&editor_area));
+        sidebar.set_resize_end_child(true);
+        sidebar.set_shrink_end_child(false);
+
+        // left side, a tab per document
+        let notebook = Notebook::new();
+        notebook.set_widget_name("documents");
+        notebook.set_scrollable(true);
+        notebook.set_show_border(false);
+        paned.set_start_child(Some(&notebook));
         paned.set_resize_start_child(true);
         paned.set_shrink_start_child(false);
 
         // right side
-        let scrolled_html = ScrolledWindow::new();
-        let html_view = TextView::new();
-        html_view.set_widget_name("static_html");
-        html_view.set_editable(false); // Make it read-only
-        html_view.set_can_focus(false); // Prevent focus on HTML view
-        html_view.set_wrap_mode(WrapMode::None); // Allow horizontal scrolling
-        
-        // Create an overlay to hold the ScrolledWindow and the theme toggle button
+        // Create an overlay to hold the previews and the theme toggle button
         let overlay = Overlay::new();
         
-        // Create the theme toggle button
-        let theme_button = Button::new();
+        // Create the theme picker button
+        let theme_button = MenuButton::new();
         theme_button.set_widget_name("theme_toggle");
         theme_button.set_halign(Align::End);
         theme_button.set_valign(Align::End);
         theme_button.set_size_request(36, 36); // Set fixed size for the button
-        
-        // Add a moon icon for the button
-        let icon = Image::from_icon_name("weather-clear-night-symbolic");
-        theme_button.set_child(Some(&icon));
+        theme_button.set_focus_on_click(false);
+        theme_button.set_icon_name("weather-clear-night-symbolic");
         
         // Use CSS to control styling
         let css_provider = CssProvider::new();
         css_provider.load_from_data(LIGHT_MODE_CSS);
         
-        // Create a separate CSS provider for the button
+        // Create a separate CSS provider for the button and the theme error banner
         let button_css_provider = CssProvider::new();
+        let font_provider = CssProvider::new();
         button_css_provider.load_from_data(THEME_BUTTON_CSS);
         
-        // Apply CSS to both text views and the window
+        // Apply CSS to the window and everything in it
         gtk4::style_context_add_provider_for_display(
             &gtk4::prelude::WidgetExt::display(&window), 
             &css_provider, 
             STYLE_PROVIDER_PRIORITY_APPLICATION
         );
-        gtk4::style_context_add_provider_for_display(
-            &gtk4::prelude::WidgetExt::display(&html_view), 
-            &css_provider, 
-            STYLE_PROVIDER_PRIORITY_APPLICATION
-        );
-        gtk4::style_context_add_provider_for_display(
-            &gtk4::prelude::WidgetExt::display(&text_view), 
-            &css_provider, 
-            STYLE_PROVIDER_PRIORITY_APPLICATION
-        );
         
         // Apply CSS to the button
         gtk4::style_context_add_provider_for_display(
@@ -318,201 +497,112 @@ impl MarkdownEditor {
             &button_css_provider, 
             STYLE_PROVIDER_PRIORITY_APPLICATION
         );
+        gtk4::style_context_add_provider_for_display(
+            &gtk4::prelude::WidgetExt::display(&window),
+            &font_provider,
+            STYLE_PROVIDER_PRIORITY_USER
+        );
         
-        // padding to make HTML view nicer
-        html_view.set_left_margin(10);
-        html_view.set_right_margin(10);
-        html_view.set_top_margin(10);
-        html_view.set_bottom_margin(10);
-        
-        // Add a click controller to the HTML view to redirect focus to markdown input
-        let click_controller = gtk4::GestureClick::new();
-        let text_view_clone = text_view.clone();
-        click_controller.connect_pressed(move |_, _, _, _| {
-            text_view_clone.grab_focus();
-        });
-        html_view.add_controller(click_controller);
-
-        let html_buffer = html_view.buffer();
-        scrolled_html.set_child(Some(&html_view));
-        
-        // Add the ScrolledWindow to the overlay
-        overlay.set_child(Some(&scrolled_html));
-        
-        // Add the button as an overlay
-        overlay.add_overlay(&theme_button);
+        let previews = Stack::new();
+        // Add the stack to the overlay
+        overlay.set_child(Some(&previews));
+
+        let preview_button = ToggleButton::new();
+        preview_button.set_widget_name("preview_toggle");
+        preview_button.set_child(Some(&Image::from_icon_name("view-reveal-symbolic")));
+        preview_button.set_tooltip_text(Some("Rendered preview (Ctrl+R)"));
+        preview_button.set_focus_on_click(false);
+        preview_button.set_action_name(Some("win.rendered-preview"));
+
+        let sync_button = ToggleButton::new();
+        sync_button.set_widget_name("sync_toggle");
+        sync_button.set_child(Some(&Image::from_icon_name("insert-link-symbolic")));
+        sync_button.set_tooltip_text(Some("Synchronized scrolling"));
+        sync_button.set_focus_on_click(false);
+        sync_button.set_action_name(Some("win.sync-scroll"));
+
+        // Add the buttons as an overlay
+        let overlay_buttons = GtkBox::new(Orientation::Horizontal, 0);
+        overlay_buttons.set_halign(Align::End);
+        overlay_buttons.set_valign(Align::End);
+        overlay_buttons.append(&format::format_toolbar());
+        overlay_buttons.append(&sync_button);
+        overlay_buttons.append(&preview_button);
+        overlay_buttons.append(&theme_button);
+        overlay.add_overlay(&overlay_buttons);
+
+        let theme_errors = Label::new(None);
+        theme_errors.set_widget_name("theme_errors");
+        theme_errors.set_halign(Align::Fill);
+        theme_errors.set_valign(Align::Start);
+        theme_errors.set_xalign(0.0);
+        theme_errors.set_wrap(true);
+        theme_errors.set_tooltip_text(Some("Click to dismiss"));
+        theme_errors.set_visible(false);
+        overlay.add_overlay(&theme_errors);
         
         // Add the overlay to the paned view
         paned.set_end_child(Some(&overlay));
         paned.set_resize_end_child(true);
         paned.set_shrink_end_child(false);
 
-        // initial empty HTML structure
-        html_buffer.set_text(&html_template.replace("{}", ""));
-        
-        // Ensure markdown input has focus when app starts
-        text_view.grab_focus();
-
-        // create the markdown chars set!
-        let mut markdown_chars = HashSet::new();
-        for c in "#*_-+[]()>`".chars() {
-            markdown_chars.insert(c);
-        }
-
-        // Create a reference to is_dark_mode for the button click handler
-        // Randomly choose initial theme with 50% chance
-        let mut rng = rand::thread_rng();
-        let initial_dark_mode = rng.gen_bool(0.5);
-        let is_dark_mode = Rc::new(RefCell::new(initial_dark_mode));
-        
-        // Apply initial theme based on random selection
-        if initial_dark_mode {
-            css_provider.load_from_data(DARK_MODE_CSS);
-            theme_button.add_css_class("dark");
-        } else {
-            css_provider.load_from_data(LIGHT_MODE_CSS);
-        }
-        
-        // Connect the button click signal
-        let is_dark_mode_clone = is_dark_mode.clone();
-        let css_provider_clone = css_provider.clone();
-        let text_view_clone = text_view.clone();
-        theme_button.connect_clicked(move |button| {
-            let mut dark_mode = is_dark_mode_clone.borrow_mut();
-            *dark_mode = !*dark_mode;
-            
-            if *dark_mode {
-                css_provider_clone.load_from_data(DARK_MODE_CSS);
-                button.add_css_class("dark");
-            } else {
-                css_provider_clone.load_from_data(LIGHT_MODE_CSS);
-                button.remove_css_class("dark");
-            }
-            
-            // Return focus to markdown input after theme change
-            text_view_clone.grab_focus();
-        });
-        
-        // Add a click controller to the theme button to prevent focus change
-        let click_controller_button = gtk4::GestureClick::new();
-        //let text_view_clone = text_view.clone();
-        click_controller_button.connect_pressed(move |_, _, _, _| {
-            // This will be called before the clicked signal
-            // We'll let the clicked signal handle the theme change
-            // and then it will restore focus
-        });
-        theme_button.add_controller(click_controller_button);
-        
-        MarkdownEditor {
+        Rc::new(MarkdownEditor {
             window,
-            md_buffer,
-            html_buffer,
-            update_pending: Rc::new(RefCell::new(false)),
-            html_template,
-            markdown_chars,
-            //is_dark_mode,
-            //css_provider,
-        }
+            sidebar,
+            project_view,
+            project_store,
+            project_title,
+            project_menu,
+            project_dir: RefCell::new(None),
+            project_monitors: RefCell::new(Vec::new()),
+            project_refresh_pending: Cell::new(false),
+            outline_expander,
+            outline_view,
+            outline_store,
+            find,
+            paned,
+            notebook,
+            documents: RefCell::new(Vec::new()),
+            previews,
+            context_menu: Menu::new(),
+            export_dir: RefCell::new(export_dir),
+            export_on_save: Rc::new(Cell::new(export_on_save)),
+            html_template: RefCell::new(html_template),
+            options: Cell::new(options),
+            update_delay: Cell::new(update::UPDATE_DELAY),
+            rendered: Cell::new(false),
+            scroll_sync: Cell::new(true),
+            preview_server,
+            settings: Rc::new(RefCell::new(Settings::load())),
+            css_provider,
+            theme: RefCell::new("system".to_string()),
+            dark: Cell::new(false),
+            font_provider,
+            theme_button,
+            theme_errors,
+            themes_monitor: RefCell::new(None),
+            settings_portal: RefCell::new(None),
+            portal_dark: Cell::new(None),
+        })
     }
 
-    fn connect_signals(&self) {
-        // clone values for use in closures
-        let md_buffer = self.md_buffer.clone();
-        let html_buffer = self.html_buffer.clone();
-        let html_template = self.html_template.clone();
-        let update_pending = self.update_pending.clone();
-        let markdown_chars = self.markdown_chars.clone();
-
-
-        // connect to insert_text signal
-        self.md_buffer.connect_insert_text(move |buffer, location, text| {
-            // get the line text including newly inserted text
-            let line = location.line();
-            let line_start = buffer.iter_at_line(line).expect("Failed to get line start"); // rust
-            let mut line_end = buffer.iter_at_line(line).expect("Failed to get line end"); // is
-            line_end.forward_to_line_end();                                                // clean
-
-            // get the complete line text after insertion
-            let mut temp_end = location.clone();
-            temp_end.forward_chars(text.len() as i32);
-            let before_insert = buffer.text(&line_start, location, false);
-            let after_insert = buffer.text(&temp_end, &line_end, false);
-            let complete_line = format!("{}{}{}", before_insert, text, after_insert);
-
-            // Check for proper list marker pattern
-            let trimmed = complete_line.trim_start();
-            let is_list_marker = trimmed.starts_with('-') || trimmed.starts_with('+') || trimmed.starts_with('*');
-
-            // Update immediately for proper markdown syntax
-            let has_markdown_char = text.chars().any(|c| markdown_chars.contains(&c));
-            
-            if is_list_marker || has_markdown_char {
-                update_preview(&md_buffer, &html_buffer, &html_template);
-            } else if !*update_pending.borrow() {
-                *update_pending.borrow_mut() = true;
-                
-                let tb = md_buffer.clone();
-                let hb = html_buffer.clone();
-                let ht = html_template.clone();
-                let up = update_pending.clone();
-                
-                timeout_add_local(Duration::from_millis(200), move || {
-                    update_preview(&tb, &hb, &ht);
-                    *up.borrow_mut() = false;
-                    glib::Continue(false)
-                });
-            }
-        });
-
-        // Connect to delete_range signal
-        let tb = self.md_buffer.clone();
-        let hb = self.html_buffer.clone();
-        let ht = self.html_template.clone();
-        let up = self.update_pending.clone();
-        
-        self.md_buffer.connect_delete_range(move |_, _, _| {
-            if !*up.borrow() {
-                *up.borrow_mut() = true;
-                
-                let tb2 = tb.clone();
-                let hb2 = hb.clone();
-                let ht2 = ht.clone();
-                let up2 = up.clone();
-                
-                timeout_add_local(Duration::from_millis(200), move || {
-                    update_preview(&tb2, &hb2, &ht2);
-                    *up2.borrow_mut() = false;
-                    glib::Continue(false)
-                });
-            }
-        });
-
-        // Connect to the changed signal to catch all modifications including paste operations
-        let tb = self.md_buffer.clone();
-        let hb = self.html_buffer.clone();
-        let ht = self.html_template.clone();
-        let up = self.update_pending.clone();
-        
-        self.md_buffer.connect_changed(move |_| {
-            if !*up.borrow() {
-                *up.borrow_mut() = true;
-                
-                let tb2 = tb.clone();
-                let hb2 = hb.clone();
-                let ht2 = ht.clone();
-                let up2 = up.clone();
-                
-                // Update immediately for paste operations
-                update_preview(&tb2, &hb2, &ht2);
-                
-                // reset update pending after delay
-                // but THERE IS NO delay Boi
-                timeout_add_local(Duration::from_millis(20), move || {
-                    *up2.borrow_mut() = false;
-                    glib::Continue(false)
-                });
+    /// Switch every document's preview between the HTML source and the rendered page.
+    fn connect_preview_toggle(self: &Rc<Self>) {
+        let rendered_action = SimpleAction::new_stateful("rendered-preview", None, &false.to_variant());
+        let editor = self.clone();
+        rendered_action.connect_activate(move |action, _| {
+            let rendered = !editor.rendered.get();
+            editor.rendered.set(rendered);
+            action.set_state(&rendered.to_variant());
+            for doc in editor.documents() {
+                doc.preview.set_visible_child_name(if rendered { "rendered" } else { "source" });
             }
+            editor.doc().text_view.grab_focus();
         });
+        self.window.add_action(&rendered_action);
+        if let Some(app) = self.window.application() {
+            app.set_accels_for_action("win.rendered-preview", &["<Control>r"]);
+        }
     }
 
     fn show_all(&self) {
@@ -520,40 +610,196 @@ impl MarkdownEditor {
     }
 }
 
-fn update_preview(md_buffer: &TextBuffer, html_buffer: &TextBuffer, html_template: &str) {
+// the whole page for what's in the markdown buffer, as shown in the HTML pane
+fn render_html(md_buffer: &TextBuffer, html_template: &str, options: &Options) -> String {
     let start = md_buffer.start_iter();
     let end = md_buffer.end_iter();
     let markdown = md_buffer.text(&start, &end, false);
 
     // convert markdown and wrap in HTML structure
-    let content = markdown_to_html(&markdown);
-    
-    // indent the content by 4 spaces to align with template
-    let indented_content: String = content
-        .lines()
-        .map(|line| if !line.trim().is_empty() { format!("    {}", line) } else { line.to_string() })
-        .collect::<Vec<String>>()
-        .join("\n");
-    
-    let html = html_template.replace("{}", &indented_content);
+    let content = markdown_to_html(&markdown, options);
+    render_page(html_template, &content, DEFAULT_ROOT)
+}
 
-    // update HTML buffer
-    html_buffer.set_text(&html);
+/// Convert a file (or stdin) and print the templated page.
+fn convert(input: Option<PathBuf>, options: &Options, html_template: &str) -> io::Result<()> {
+    let markdown = match input {
+        Some(path) => fs::read_to_string(path)?,
+        None => {
+            let mut markdown = String::new();
+            io::stdin().read_to_string(&mut markdown)?;
+            markdown
+        }
+    };
+
+    let content = markdown_to_html(&markdown, options);
+    println!("{}", render_page(html_template, &content, DEFAULT_ROOT));
+    Ok(())
 }
 
 fn main() {
-    // initialization
-    let application = Application::builder()
-        .application_id("x.LiL.quikMD")
-        .build();
-
-    application.connect_activate(|app| {
-        let editor = MarkdownEditor::new(app);
-        editor.connect_signals();
+    let config = Config::load();
+    let args = match cli::parse_args(std::env::args().skip(1), &config) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("entermd: {}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let options = args.options;
+
+    match args.command {
+        Command::Help => println!("{}", cli::USAGE),
+        Command::Convert(input) => {
+            if let Err(err) = convert(input, &options, &config.html_template()) {
+                eprintln!("entermd: {}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::Build { src, out } => {
+            match site::build_site(&src, &out, &options, &config.html_template()) {
+                Ok(report) => {
+                    for (path, err) in &report.errors {
+                        eprintln!("entermd: {}: {}", path.display(), err);
+                    }
+                    println!(
+                        "rendered {} pages ({} unchanged), copied {} files, removed {} into {}",
+                        report.rendered.len(), report.unchanged, report.copied.len(),
+                        report.removed.len(), out.display()
+                    );
+                    if !report.errors.is_empty() {
+                        std::process::exit(1);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("entermd: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Watch { src, out } => {
+            if let Err(err) = watch::watch_site(&src, &out, &options, &config.html_template(), |_| {}) {
+                eprintln!("entermd: {}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::Serve { src, out } => {
+            let server = start_preview_server(&out, args.port);
+            println!("serving {} at {}", out.display(), server.url());
+            if let Err(err) = watch::watch_site(&src, &out, &options, &config.html_template(), |report| server.reload_built(report)) {
+                eprintln!("entermd: {}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::Editor(editor_args) => {
+            let application = Application::builder()
+                .application_id(APP_ID)
+                .flags(gio::ApplicationFlags::HANDLES_OPEN)
+                .build();
+            // find out if an editor is already running before serving anything of our own
+            if let Err(err) = application.register(None::<&gio::Cancellable>) {
+                eprintln!("entermd: {}", err);
+                std::process::exit(1);
+            }
+            if application.is_remote() {
+                let ignored = ignored_flags(&editor_args);
+                if !ignored.is_empty() {
+                    eprintln!("entermd: opening in the editor that's already running, which ignores {}", ignored.join(", "));
+                }
+                application.run_with_args(&gtk_args(&editor_args.files));
+                return;
+            }
+
+            let preview_server = editor_args.serve.clone().map(|root| {
+                let server = start_preview_server(&root, args.port);
+                println!("serving {} at {}, live buffer at {}", root.display(), server.url(), LIVE_PAGE_PATH);
+                // css or images changing under the site should show up too
+                let watched = server.clone();
+                std::thread::spawn(move || {
+                    if let Err(err) = watch::watch_tree(&root, None, || watched.reload()) {
+                        eprintln!("entermd: {}", err);
+                    }
+                });
+                server
+            });
+            run_editor(application, *editor_args, options, preview_server, config);
+        }
+    }
+}
+
+fn start_preview_server(root: &Path, port: u16) -> PreviewServer {
+    PreviewServer::start(root, port).unwrap_or_else(|err| {
+        eprintln!("entermd: can't serve on port {}: {}", port, err);
+        std::process::exit(1);
+    })
+}
+
+// one instance per user session, a second `entermd` hands its files over to it
+const APP_ID: &str = "x.LiL.quikMD";
+
+// the flags a second `entermd` can't pass on to the running editor
+fn ignored_flags(args: &EditorArgs) -> Vec<&'static str> {
+    [
+        (args.serve.is_some(), "--serve"),
+        (args.window.is_some(), "--window"),
+        (args.layout.is_some(), "--layout"),
+        (args.export_on_save, "--export-on-save"),
+        (args.overrides.converter != ConverterConfig::default(), "--profile"),
+        (args.overrides.output.is_some(), "--out"),
+    ]
+    .into_iter()
+    .filter_map(|(given, flag)| given.then_some(flag))
+    .collect()
+}
+
+// our own flags are already handled, GTK only gets the files, which it
+// passes to whichever instance is running
+fn gtk_args(files: &[PathBuf]) -> Vec<String> {
+    std::env::args()
+        .take(1)
+        .chain(files.iter().map(|path| path.to_string_lossy().into_owned()))
+        .collect()
+}
+
+fn run_editor(application: Application, args: EditorArgs, options: Options, preview_server: Option<PreviewServer>, config: Config) {
+    let EditorArgs { files, serve, export_dir, export_on_save, window, layout, overrides } = args;
+    // the site being served, or wherever the project's entermd.toml is, or here
+    let project = serve
+        .or_else(|| config::project_path().and_then(|path| path.parent().map(Path::to_path_buf)))
+        .unwrap_or_else(|| PathBuf::from("."));
+    // the window is made the first time it's asked for, by a launch with or without files
+    let editor: Rc<RefCell<Option<Rc<MarkdownEditor>>>> = Rc::new(RefCell::new(None));
+    let start = Rc::new(move |app: &Application| -> Rc<MarkdownEditor> {
+        if let Some(editor) = editor.borrow().as_ref() {
+            return editor.clone();
+        }
+        let editor_ref = editor.clone();
+        let editor = MarkdownEditor::new(app, options, preview_server.clone(), export_dir.clone(), export_on_save);
+        editor.connect_preview_toggle();
+        editor.connect_file_actions();
+        editor.connect_tabs();
+        editor.new_document();
+        editor.connect_sidebar(project.clone());
+        editor.connect_outline();
+        editor.connect_find();
+        editor.connect_window_state(window, layout);
+        editor.connect_scroll_sync();
+        editor.connect_format_actions();
+        editor.connect_theme(&config);
+        // last, so its keybindings win over the defaults
+        editor.connect_preferences(&config, overrides.clone());
         editor.show_all();
+        *editor_ref.borrow_mut() = Some(editor.clone());
+        editor
     });
 
-    
+    let start_clone = start.clone();
+    application.connect_activate(move |app| start_clone(app).window.present());
+    application.connect_open(move |app, files, _| {
+        let editor = start(app);
+        editor.open_files(files);
+        editor.window.present();
+    });
 
-    application.run();
+    application.run_with_args(&gtk_args(&files));
 }
@@ -1,8 +1,10 @@
 use regex::Regex;
 use lazy_static::lazy_static;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum Block {
     Paragraph(String),
     List(Vec<(usize, String)>),
@@ -22,23 +24,213 @@ struct SplitState {
     current: Option<CurrentBlock>,
 }
 
-pub fn markdown_to_html(markdown: &str) -> String {
-    let blocks = split_blocks(markdown);
-    blocks.into_iter()
-        .map(parse_block)
-        .collect::<Vec<_>>()
-        .join("\n") + "\n"
+/// Per-extension toggles for the syntax layered on top of plain markdown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Options {
+    /// pipe tables (`| a | b |` followed by a `---` separator row)
+    pub tables: bool,
+    /// `~~struck~~` text
+    pub strikethrough: bool,
+    /// `{width=.. height=..}` braces after an image
+    pub image_size: bool,
+    /// `--` collapsed into a single `-`
+    pub dash_replacement: bool,
 }
 
-fn split_blocks(markdown: &str) -> Vec<Block> {
+impl Default for Options {
+    // entermd's own flavour: every extension on
+    fn default() -> Self {
+        Options {
+            tables: true,
+            strikethrough: true,
+            image_size: true,
+            dash_replacement: true,
+        }
+    }
+}
+
+impl Options {
+    /// Strict CommonMark: no extensions at all.
+    pub fn commonmark() -> Self {
+        Options {
+            tables: false,
+            strikethrough: false,
+            image_size: false,
+            dash_replacement: false,
+        }
+    }
+
+    /// GitHub flavoured: tables and strikethrough only.
+    pub fn gfm() -> Self {
+        Options {
+            tables: true,
+            strikethrough: true,
+            ..Options::commonmark()
+        }
+    }
+
+    /// Look up a preset by name ("entermd", "commonmark" or "gfm").
+    pub fn from_profile(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "entermd" | "default" => Some(Options::default()),
+            "commonmark" | "strict" => Some(Options::commonmark()),
+            "gfm" | "github" => Some(Options::gfm()),
+            _ => None,
+        }
+    }
+}
+
+/// Where one block of the converted html came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceBlock {
+    /// markdown lines the block was made from, counting from 0
+    pub source: Range<usize>,
+    /// lines of the converted html it became
+    pub html: Range<usize>,
+}
+
+/// A heading of the document, for the outline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    pub level: u8,
+    /// as written, closing `#`s left off
+    pub text: String,
+    /// the markdown line it's on, counting from 0
+    pub line: usize,
+}
+
+/// Something in the markdown that converts, just probably not the way it was meant to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// the markdown line it's about, counting from 0
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line + 1, self.message)
+    }
+}
+
+/// Every heading in `markdown`, in order. Lines that only look like headings inside code blocks are left out.
+pub fn headings(markdown: &str) -> Vec<Heading> {
+    split_blocks(markdown).0.into_iter()
+        .filter_map(|(block, source)| match block {
+            Block::Header(level, text) => Some(Heading {
+                level,
+                text: without_closing_hashes(&text).to_string(),
+                line: source.start,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+// `## Title ##` is just "Title", but `# C#` keeps its `#`
+fn without_closing_hashes(text: &str) -> &str {
+    let trimmed = text.trim_end_matches('#');
+    if trimmed.len() == text.len() || !(trimmed.is_empty() || trimmed.ends_with(char::is_whitespace)) {
+        return text;
+    }
+    trimmed.trim_end()
+}
+
+pub fn markdown_to_html(markdown: &str, options: &Options) -> String {
+    let blocks = split_blocks(markdown).0.into_iter()
+        .map(|(block, source)| (parse_block(block, options), source));
+    join_blocks(blocks).0
+}
+
+/// Converts a document over and over as it's edited, only parsing blocks that
+/// changed since the last conversion.
+pub struct IncrementalConverter {
+    options: Options,
+    // html for every block of the last conversion
+    cache: HashMap<Block, String>,
+}
+
+impl IncrementalConverter {
+    pub fn new(options: Options) -> Self {
+        IncrementalConverter {
+            options,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Convert like [`markdown_to_html`], also returning which markdown lines produced each
+    /// block of html and anything that looks like a mistake.
+    ///
+    /// `cancelled` is checked between blocks; once it returns true the conversion
+    /// is abandoned and `None` returned.
+    pub fn convert(&mut self, markdown: &str, cancelled: impl Fn() -> bool) -> Option<(String, Vec<SourceBlock>, Vec<Warning>)> {
+        let mut previous = std::mem::take(&mut self.cache);
+        let mut blocks = Vec::new();
+        let (split, warnings) = split_blocks(markdown);
+        for (block, source) in split {
+            if cancelled() {
+                // keep what we had for next time
+                self.cache.extend(previous);
+                return None;
+            }
+            let html = match self.cache.get(&block) {
+                Some(html) => html.clone(),
+                None => {
+                    // blocks that are gone fall out of the cache with `previous`
+                    let html = previous.remove(&block).unwrap_or_else(|| parse_block(block.clone(), &self.options));
+                    self.cache.insert(block, html.clone());
+                    html
+                }
+            };
+            blocks.push((html, source));
+        }
+        let (html, map) = join_blocks(blocks.into_iter());
+        Some((html, map, warnings))
+    }
+}
+
+// the converted blocks as one document, and where each one came from
+fn join_blocks(blocks: impl Iterator<Item = (String, Range<usize>)>) -> (String, Vec<SourceBlock>) {
+    let mut html = Vec::new();
+    let mut map = Vec::new();
+    let mut html_line = 0;
+    for (block_html, source) in blocks {
+        let line_count = block_html.matches('\n').count() + 1;
+        map.push(SourceBlock { source, html: html_line..html_line + line_count });
+        html_line += line_count;
+        html.push(block_html);
+    }
+    (html.join("\n") + "\n", map)
+}
+
+// blocks along with the markdown lines each was made from, and what looked off on the way
+fn split_blocks(markdown: &str) -> (Vec<(Block, Range<usize>)>, Vec<Warning>) {
     let lines = markdown.lines().map(|s| s.trim_end()).collect::<Vec<_>>();
-    let initial_state = SplitState {
+    let line_count = lines.len();
+    let mut state = SplitState {
         blocks: Vec::new(),
         current: None,
     };
-    let mut state = lines.into_iter().fold(initial_state, |state, line| {
-        split_blocks_reducer(state, line)
-    });
+    let mut sources = Vec::new();
+    let mut warnings = Vec::new();
+    // first line of the block being built up in `state.current`
+    let mut current_start = 0;
+    for (i, line) in lines.into_iter().enumerate() {
+        let was_building = state.current.is_some();
+        let finished = state.blocks.len();
+        state = split_blocks_reducer(state, line);
+        for block in &state.blocks[finished..] {
+            sources.push(match block {
+                Block::Header(..) | Block::Hr => i..i + 1,
+                // the closing fence is part of the block
+                Block::Code(_) => current_start..i + 1,
+                _ => current_start..i,
+            });
+        }
+        if state.current.is_some() && (!was_building || state.blocks.len() > finished) {
+            current_start = i;
+        }
+    }
 
     // Handle remaining current block
     if let Some(current) = state.current.take() {
@@ -50,11 +242,16 @@ fn split_blocks(markdown: &str) -> Vec<Block> {
             CurrentBlock::List(items) => {
                 state.blocks.push(Block::List(items));
             }
-            CurrentBlock::Code(_) => unreachable!(),
+            // a fence that's never closed runs to the end of the document
+            CurrentBlock::Code(lines) => {
+                warnings.push(Warning { line: current_start, message: "the code block is never closed".to_string() });
+                state.blocks.push(Block::Code(lines.join("\n")));
+            }
         }
+        sources.push(current_start..line_count);
     }
 
-    state.blocks
+    (state.blocks.into_iter().zip(sources).collect(), warnings)
 }
 
 fn split_blocks_reducer(state: SplitState, line: &str) -> SplitState {
@@ -212,54 +409,52 @@ fn split_blocks_reducer(state: SplitState, line: &str) -> SplitState {
     new_state
 }
 
-fn parse_block(block: Block) -> String {
+fn parse_block(block: Block, options: &Options) -> String {
     match block {
-        Block::Paragraph(content) => parse_paragraph(&content),
-        Block::List(items) => parse_list(items),
-        Block::Header(level, text) => format!("<h{}>{}</h{}>", level, inline_parse(&text), level),
+        Block::Paragraph(content) => parse_paragraph(&content, options),
+        Block::List(items) => parse_list(items, options),
+        Block::Header(level, text) => format!("<h{}>{}</h{}>", level, inline_parse(&text, options), level),
         Block::Code(content) => format!("<pre><code>{}</code></pre>", content),
         Block::Hr => "<hr>".to_string(),
     }
 }
 
-fn parse_paragraph(content: &str) -> String {
+fn parse_paragraph(content: &str, options: &Options) -> String {
     let lines: Vec<&str> = content.split('\n').collect();
-    if lines.len() >= 2 {
-        if is_table_header(lines[0]) && is_table_separator(lines[1]) {
-            let header_cells = parse_table_row(lines[0]);
-            let separator_cells = parse_table_row(lines[1]);
-            if header_cells.len() == separator_cells.len() {
-                let body_lines = &lines[2..];
-                if body_lines.iter().all(|line| is_table_row(line)) {
-                    let body_rows: Vec<Vec<String>> = body_lines.iter().map(|line| parse_table_row(line)).collect();
-                    if body_rows.iter().all(|row| row.len() == header_cells.len()) {
-                        let mut html = vec!["<table>".to_string()];
-                        html.push("  <thead>".to_string());
-                        html.push("    <tr>".to_string());
-                        for cell in header_cells {
-                            html.push(format!("      <th>{}</th>", inline_parse(&cell)));
-                        }
-                        html.push("    </tr>".to_string());
-                        html.push("  </thead>".to_string());
-                        if !body_rows.is_empty() {
-                            html.push("  <tbody>".to_string());
-                            for row in body_rows {
-                                html.push("    <tr>".to_string());
-                                for cell in row {
-                                    html.push(format!("      <td>{}</td>", inline_parse(&cell)));
-                                }
-                                html.push("    </tr>".to_string());
+    if options.tables && lines.len() >= 2 && is_table_header(lines[0]) && is_table_separator(lines[1]) {
+        let header_cells = parse_table_row(lines[0]);
+        let separator_cells = parse_table_row(lines[1]);
+        if header_cells.len() == separator_cells.len() {
+            let body_lines = &lines[2..];
+            if body_lines.iter().all(|line| is_table_row(line)) {
+                let body_rows: Vec<Vec<String>> = body_lines.iter().map(|line| parse_table_row(line)).collect();
+                if body_rows.iter().all(|row| row.len() == header_cells.len()) {
+                    let mut html = vec!["<table>".to_string()];
+                    html.push("  <thead>".to_string());
+                    html.push("    <tr>".to_string());
+                    for cell in header_cells {
+                        html.push(format!("      <th>{}</th>", inline_parse(&cell, options)));
+                    }
+                    html.push("    </tr>".to_string());
+                    html.push("  </thead>".to_string());
+                    if !body_rows.is_empty() {
+                        html.push("  <tbody>".to_string());
+                        for row in body_rows {
+                            html.push("    <tr>".to_string());
+                            for cell in row {
+                                html.push(format!("      <td>{}</td>", inline_parse(&cell, options)));
                             }
-                            html.push("  </tbody>".to_string());
+                            html.push("    </tr>".to_string());
                         }
-                        html.push("</table>".to_string());
-                        return html.join("\n");
+                        html.push("  </tbody>".to_string());
                     }
+                    html.push("</table>".to_string());
+                    return html.join("\n");
                 }
             }
         }
     }
-    format!("<p>{}</p>", inline_parse(content))
+    format!("<p>{}</p>", inline_parse(content, options))
 }
 
 fn is_table_header(line: &str) -> bool {
@@ -286,7 +481,7 @@ fn is_table_row(line: &str) -> bool {
     line.trim().contains('|')
 }
 
-fn parse_list(items: Vec<(usize, String)>) -> String {
+fn parse_list(items: Vec<(usize, String)>, options: &Options) -> String {
     if items.is_empty() {
         return "<ul></ul>".to_string();
     }
@@ -333,7 +528,7 @@ fn parse_list(items: Vec<(usize, String)>) -> String {
         }
 
         let indent_str = "  ".repeat(normalized_indent as usize);
-        result.push(format!("{}<li>{}", indent_str, inline_parse(content)));
+        result.push(format!("{}<li>{}", indent_str, inline_parse(content, options)));
 
         current_level = normalized_indent;
     }
@@ -347,36 +542,44 @@ fn parse_list(items: Vec<(usize, String)>) -> String {
     result.join("\n")
 }
 
-fn inline_parse(text: &str) -> String {
-    let text = text.replace("--", "-");
+fn inline_parse(text: &str, options: &Options) -> String {
+    let text = if options.dash_replacement {
+        text.replace("--", "-")
+    } else {
+        text.to_string()
+    };
 
     // handle images 
     // also handle resizing of image
     lazy_static! {
         static ref IMAGE_SIZE_REGEX: Regex = Regex::new(r"!\[(.*?)\]\((.*?)\)\{(.*?)\}").unwrap();
     }
-    let text = IMAGE_SIZE_REGEX.replace_all(&text, |caps: &regex::Captures| {
-        let alt = caps.get(1).unwrap().as_str();
-        let url = caps.get(2).unwrap().as_str();
-        let attrs = caps.get(3).unwrap().as_str();
-
-        let mut img_tag = format!("<img src=\"{}\" alt=\"{}\"", url, alt);
-
-        // extract width height
-        lazy_static! {
-            static ref WIDTH_REGEX: Regex = Regex::new(r"width=(\d+)").unwrap();
-            static ref HEIGHT_REGEX: Regex = Regex::new(r"height=(\d+)").unwrap();
-        }
-        if let Some(cap) = WIDTH_REGEX.captures(attrs) {
-            img_tag.push_str(&format!(" width=\"{}\"", &cap[1]));
-        }
-        if let Some(cap) = HEIGHT_REGEX.captures(attrs) {
-            img_tag.push_str(&format!(" height=\"{}\"", &cap[1]));
-        }
+    let text = if options.image_size {
+        IMAGE_SIZE_REGEX.replace_all(&text, |caps: &regex::Captures| {
+            let alt = caps.get(1).unwrap().as_str();
+            let url = caps.get(2).unwrap().as_str();
+            let attrs = caps.get(3).unwrap().as_str();
+
+            let mut img_tag = format!("<img src=\"{}\" alt=\"{}\"", url, alt);
+
+            // extract width height
+            lazy_static! {
+                static ref WIDTH_REGEX: Regex = Regex::new(r"width=(\d+)").unwrap();
+                static ref HEIGHT_REGEX: Regex = Regex::new(r"height=(\d+)").unwrap();
+            }
+            if let Some(cap) = WIDTH_REGEX.captures(attrs) {
+                img_tag.push_str(&format!(" width=\"{}\"", &cap[1]));
+            }
+            if let Some(cap) = HEIGHT_REGEX.captures(attrs) {
+                img_tag.push_str(&format!(" height=\"{}\"", &cap[1]));
+            }
 
-        img_tag.push('>');
-        img_tag
-    });
+            img_tag.push('>');
+            img_tag
+        }).into_owned()
+    } else {
+        text
+    };
 
     // handle line breaks
     lazy_static! {
@@ -384,20 +587,89 @@ fn inline_parse(text: &str) -> String {
     }
     let text = LINE_BREAK_REGEX.replace_all(&text, "<br>\n");
 
-    // Other inline elements
-    let replacements = [
-        (Regex::new(r"\*\*(.+?)\*\*").unwrap(), r"<strong>$1</strong>"),
-        (Regex::new(r"\*(.+?)\*").unwrap(), r"<em>$1</em>"),
-        (Regex::new(r"~~(.+?)~~").unwrap(), r"<s>$1</s>"),
-        (Regex::new(r"!\[(.*?)\]\((.*?)\)").unwrap(), "<img src=\"$2\"alt=\"$1\">"),
-        (Regex::new(r"\[(.*?)\]\((.*?)\)").unwrap(), "<a href=\"$2\">$1</a>"),
-        (Regex::new(r"`(.+?)`").unwrap(), r"<code>$1</code>"),
-    ];
+    // Other inline elements, compiled once since every block goes through here
+    lazy_static! {
+        static ref INLINE_REGEXES: [(Regex, &'static str); 6] = [
+            (Regex::new(r"\*\*(.+?)\*\*").unwrap(), r"<strong>$1</strong>"),
+            (Regex::new(r"\*(.+?)\*").unwrap(), r"<em>$1</em>"),
+            (Regex::new(r"~~(.+?)~~").unwrap(), r"<s>$1</s>"),
+            (Regex::new(r"!\[(.*?)\]\((.*?)\)").unwrap(), "<img src=\"$2\"alt=\"$1\">"),
+            (Regex::new(r"\[(.*?)\]\((.*?)\)").unwrap(), "<a href=\"$2\">$1</a>"),
+            (Regex::new(r"`(.+?)`").unwrap(), r"<code>$1</code>"),
+        ];
+    }
+    // whether each of those is turned on
+    let enabled = [true, true, options.strikethrough, true, true, true];
 
     let mut parsed = text.to_string();
-    for (regex, replacement) in replacements.iter() {
+    for ((regex, replacement), enabled) in INLINE_REGEXES.iter().zip(enabled) {
+        if !enabled {
+            continue;
+        }
         parsed = regex.replace_all(&parsed, *replacement).to_string();
     }
 
     parsed
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incremental_matches_a_full_conversion() {
+        let options = Options::default();
+        let mut converter = IncrementalConverter::new(options);
+        let before = "# Title\n\nfirst\n\n- a\n- b\n";
+        let after = "# Title\n\nfirst, edited\n\n- a\n- b\n";
+        for markdown in [before, after] {
+            let (html, _, _) = converter.convert(markdown, || false).unwrap();
+            assert_eq!(html, markdown_to_html(markdown, &options));
+        }
+        // the old paragraph is dropped, the unchanged blocks kept
+        assert_eq!(converter.cache.len(), 3);
+    }
+
+    #[test]
+    fn cancelling_keeps_the_cache() {
+        let mut converter = IncrementalConverter::new(Options::default());
+        converter.convert("one\n\ntwo\n", || false).unwrap();
+        assert!(converter.convert("one\n\nthree\n", || true).is_none());
+        assert_eq!(converter.cache.len(), 2);
+    }
+
+    #[test]
+    fn profiles_by_name() {
+        assert_eq!(Options::from_profile("GFM"), Some(Options::gfm()));
+        assert_eq!(Options::from_profile("strict"), Some(Options::commonmark()));
+        assert_eq!(Options::from_profile("entermd"), Some(Options::default()));
+        assert_eq!(Options::from_profile("markdown2"), None);
+    }
+
+    fn sources(markdown: &str) -> Vec<Range<usize>> {
+        split_blocks(markdown).0.into_iter().map(|(_, source)| source).collect()
+    }
+
+    #[test]
+    fn split_ranges_cover_each_block() {
+        let markdown = "# Title\n\nsome text\nmore text\n\n- one\n- two\n---\n```\ncode\n```\nlast";
+        assert_eq!(sources(markdown), vec![0..1, 2..4, 5..7, 7..8, 8..11, 11..12]);
+    }
+
+    #[test]
+    fn unclosed_fence_runs_to_the_end() {
+        let (blocks, warnings) = split_blocks("text\n\n```\ncode\n# not a heading");
+        assert_eq!(blocks.last(), Some(&(Block::Code("code\n# not a heading".to_string()), 2..5)));
+        assert_eq!(warnings.iter().map(|warning| warning.to_string()).collect::<Vec<_>>(), vec!["line 3: the code block is never closed"]);
+        assert!(split_blocks("```\ncode\n```").1.is_empty());
+        assert_eq!(markdown_to_html("```\ncode", &Options::default()), "<pre><code>code</code></pre>\n");
+        assert!(headings("# Title\n```\n# code").iter().all(|heading| heading.text == "Title"));
+    }
+
+    #[test]
+    fn heading_text_drops_only_a_closing_sequence() {
+        let texts = headings("# C#\n## Title ##\n### Title#\n# ##\n")
+            .into_iter().map(|heading| heading.text).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["C#", "Title", "Title#", ""]);
+    }
+}
@@ -0,0 +1,281 @@
+use gtk4::prelude::*;
+use gtk4::gdk::{ContentProvider, DragAction};
+use gtk4::gio::SimpleAction;
+use gtk4::{
+    CellRendererText, DragSource, DropTarget, Expander, PolicyType, ScrolledWindow, TreeIter, TreeStore, TreeView,
+    TreeViewColumn, TreeViewDropPosition,
+};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::document::Document;
+use crate::markdown::{headings, Heading};
+use crate::update::replace_changed;
+use crate::MarkdownEditor;
+
+// columns of the outline
+const TEXT: u32 = 0;
+const LINE: u32 = 1;
+const LEVEL: u32 = 2;
+
+/// The outline of the current document, collapsed under the project files.
+pub fn outline_panel() -> (Expander, TreeView) {
+    let store = TreeStore::new(&[String::static_type(), i32::static_type(), u32::static_type()]);
+    let view = TreeView::with_model(&store);
+    view.set_widget_name("outline");
+    view.set_headers_visible(false);
+    view.set_activate_on_single_click(true);
+    view.set_search_column(TEXT as i32);
+    let column = TreeViewColumn::new();
+    let text = CellRendererText::new();
+    text.set_ellipsize(pango::EllipsizeMode::End);
+    column.pack_start(&text, true);
+    column.add_attribute(&text, "text", TEXT as i32);
+    view.append_column(&column);
+
+    let scrolled = ScrolledWindow::new();
+    scrolled.set_policy(PolicyType::Never, PolicyType::Automatic);
+    scrolled.set_vexpand(true);
+    scrolled.set_child(Some(&view));
+    let expander = Expander::new(Some("Outline"));
+    expander.set_widget_name("outline_expander");
+    expander.set_child(Some(&scrolled));
+    (expander, view)
+}
+
+// the lines from `headings[index]` up to the next heading at its level or above, end exclusive
+fn section(headings: &[Heading], index: usize, line_count: usize) -> (usize, usize) {
+    let heading = &headings[index];
+    let end = headings[index + 1..].iter()
+        .find(|next| next.level <= heading.level)
+        .map_or(line_count, |next| next.line);
+    (heading.line, end)
+}
+
+impl MarkdownEditor {
+    /// Clicking a heading in the outline puts the cursor on it, dragging one moves its
+    /// whole section, and `win.outline` (Ctrl+Shift+O) opens and closes the panel.
+    pub(crate) fn connect_outline(self: &Rc<Self>) {
+        let expanded = self.settings.borrow().window.outline;
+        self.outline_expander.set_expanded(expanded);
+        let show = SimpleAction::new_stateful("outline", None, &expanded.to_variant());
+        let expander = self.outline_expander.clone();
+        show.connect_activate(move |_, _| expander.set_expanded(!expander.is_expanded()));
+        self.window.add_action(&show);
+        let editor = self.clone();
+        self.outline_expander.connect_expanded_notify(move |expander| {
+            editor.settings.borrow_mut().window.outline = expander.is_expanded();
+            show.set_state(&expander.is_expanded().to_variant());
+        });
+        if let Some(app) = self.window.application() {
+            app.set_accels_for_action("win.outline", &["<Control><Shift>o"]);
+        }
+
+        let editor = self.clone();
+        self.outline_view.connect_row_activated(move |_, path, _| {
+            let Some(iter) = editor.outline_store.iter(path) else {
+                return;
+            };
+            let line = editor.outline_store.get::<i32>(&iter, LINE as i32);
+            let doc = editor.doc();
+            if let Some(iter) = doc.md_buffer.iter_at_line(line) {
+                doc.md_buffer.place_cursor(&iter);
+                doc.text_view.scroll_to_mark(&doc.md_buffer.get_insert(), 0.1, true, 0.0, 0.0);
+            }
+            doc.text_view.grab_focus();
+        });
+
+        // the heading's line travels with the drag
+        let drag = DragSource::new();
+        drag.set_actions(DragAction::MOVE);
+        let editor = self.clone();
+        drag.connect_prepare(move |_, x, y| {
+            let view = &editor.outline_view;
+            let (x, y) = view.convert_widget_to_bin_window_coords(x as i32, y as i32);
+            let Some((Some(path), _, _, _)) = view.path_at_pos(x, y) else {
+                return None;
+            };
+            let iter = editor.outline_store.iter(&path)?;
+            let line = editor.outline_store.get::<i32>(&iter, LINE as i32);
+            Some(ContentProvider::for_value(&line.to_value()))
+        });
+        self.outline_view.add_controller(drag);
+
+        let drop = DropTarget::new(i32::static_type(), DragAction::MOVE);
+        let view = self.outline_view.clone();
+        drop.connect_motion(move |_, x, y| {
+            match view.dest_row_at_pos(x as i32, y as i32) {
+                Some((Some(path), position)) => view.set_drag_dest_row(Some(&path), position),
+                _ => view.set_drag_dest_row(None, TreeViewDropPosition::Before),
+            }
+            DragAction::MOVE
+        });
+        let view = self.outline_view.clone();
+        drop.connect_leave(move |_| view.set_drag_dest_row(None, TreeViewDropPosition::Before));
+        let editor = self.clone();
+        drop.connect_drop(move |_, value, x, y| {
+            let view = &editor.outline_view;
+            view.set_drag_dest_row(None, TreeViewDropPosition::Before);
+            let (Ok(line), Some((Some(path), position))) = (value.get::<i32>(), view.dest_row_at_pos(x as i32, y as i32)) else {
+                return false;
+            };
+            let Some(iter) = editor.outline_store.iter(&path) else {
+                return false;
+            };
+            let target = editor.outline_store.get::<i32>(&iter, LINE as i32);
+            let after = matches!(position, TreeViewDropPosition::After | TreeViewDropPosition::IntoOrAfter);
+            move_section(&editor.doc(), line as usize, target as usize, after)
+        });
+        self.outline_view.add_controller(drop);
+    }
+
+    /// Show `doc`'s headings, keeping the sections that were folded folded.
+    pub(crate) fn show_outline(&self, doc: &Document) {
+        let outline = doc.outline.borrow();
+        let store = &self.outline_store;
+
+        // nothing but line numbers changed, which is most edits
+        let mut rows = Vec::new();
+        store.foreach(|_, _, iter| {
+            rows.push((*iter, store.get::<String>(iter, TEXT as i32), store.get::<u32>(iter, LEVEL as i32)));
+            false
+        });
+        let same = rows.iter().zip(outline.iter())
+            .all(|((_, text, level), heading)| *text == heading.text && *level == heading.level as u32);
+        if rows.len() == outline.len() && same {
+            for ((iter, _, _), heading) in rows.iter().zip(outline.iter()) {
+                store.set(iter, &[(LINE, &(heading.line as i32))]);
+            }
+            return;
+        }
+
+        let mut folded = HashSet::new();
+        store.foreach(|_, path, iter| {
+            if store.iter_has_child(iter) && !self.outline_view.row_expanded(path) {
+                folded.insert(store.get::<String>(iter, TEXT as i32));
+            }
+            false
+        });
+
+        store.clear();
+        // the headings each new one could go under, innermost last
+        let mut parents: Vec<(u8, TreeIter)> = Vec::new();
+        for heading in outline.iter() {
+            while parents.last().is_some_and(|(level, _)| *level >= heading.level) {
+                parents.pop();
+            }
+            let iter = store.insert_with_values(
+                parents.last().map(|(_, iter)| iter),
+                None,
+                &[(TEXT, &heading.text), (LINE, &(heading.line as i32)), (LEVEL, &(heading.level as u32))],
+            );
+            parents.push((heading.level, iter));
+        }
+
+        self.outline_view.expand_all();
+        store.foreach(|_, path, iter| {
+            if folded.contains(&store.get::<String>(iter, TEXT as i32)) {
+                self.outline_view.collapse_row(path);
+            }
+            false
+        });
+    }
+}
+
+// move the section under the heading on `line` before the heading on `target`, or after
+// its section; false if it'd land inside itself
+fn move_section(doc: &Document, line: usize, target: usize, after: bool) -> bool {
+    let buffer = &doc.md_buffer;
+    // the buffer may have moved on since the outline was made
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+    let Some((moved, new_start)) = section_moved(&text, line, target, after) else {
+        return false;
+    };
+
+    buffer.begin_user_action();
+    replace_changed(buffer, &moved);
+    buffer.end_user_action();
+
+    if let Some(iter) = buffer.iter_at_line(new_start as i32) {
+        buffer.place_cursor(&iter);
+        doc.text_view.scroll_to_mark(&buffer.get_insert(), 0.1, true, 0.0, 0.0);
+    }
+    true
+}
+
+// `text` with the section moved as `move_section` describes, and the line it now starts on
+fn section_moved(text: &str, line: usize, target: usize, after: bool) -> Option<(String, usize)> {
+    let headings = headings(text);
+    // lines as the buffer counts them, a final newline starting an empty one
+    let line_count = text.split('\n').count();
+    let from = headings.iter().position(|heading| heading.line == line)?;
+    let to = headings.iter().position(|heading| heading.line == target)?;
+    let (start, end) = section(&headings, from, line_count);
+    let to = if after { section(&headings, to, line_count).1 } else { headings[to].line };
+    if (start..=end).contains(&to) {
+        return None;
+    }
+
+    let mut lines: Vec<String> = text.split_inclusive('\n').map(str::to_string).collect();
+    let (start, end) = (start.min(lines.len()), end.min(lines.len()));
+    let mut moved: Vec<String> = lines.drain(start..end).collect();
+    // every line needs ending once it's moved; the document's last may not be
+    for line in moved.iter_mut().chain(lines.last_mut()) {
+        if !line.ends_with('\n') {
+            line.push('\n');
+        }
+    }
+    // moving down, `to` counted the section's own lines, which are gone now
+    let new_start = if to > end { to - (end - start) } else { to }.min(lines.len());
+    lines.splice(new_start..new_start, moved);
+
+    let mut moved = lines.concat();
+    // and the document ends the way it did, without an extra empty line
+    if !text.ends_with('\n') {
+        moved.pop();
+    }
+    Some((moved, new_start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn section_ends_at_the_next_heading_as_high() {
+        let outline = headings("# A\ntext\n## B\n### C\n## D\n# E\nend");
+        assert_eq!(section(&outline, 0, 7), (0, 5));
+        assert_eq!(section(&outline, 1, 7), (2, 4));
+        assert_eq!(section(&outline, 2, 7), (3, 4));
+        assert_eq!(section(&outline, 4, 7), (5, 7));
+    }
+
+    const DOCUMENT: &str = "# A\na\n# B\nb\n# C\nc";
+
+    #[test]
+    fn sections_move_up() {
+        assert_eq!(section_moved(DOCUMENT, 2, 0, false), Some(("# B\nb\n# A\na\n# C\nc".to_string(), 0)));
+        assert_eq!(section_moved("# A\na\n# B\nb\n", 2, 0, false), Some(("# B\nb\n# A\na\n".to_string(), 0)));
+    }
+
+    #[test]
+    fn sections_move_down() {
+        assert_eq!(section_moved(DOCUMENT, 0, 2, true), Some(("# B\nb\n# A\na\n# C\nc".to_string(), 2)));
+        assert_eq!(section_moved(DOCUMENT, 0, 4, true), Some(("# B\nb\n# C\nc\n# A\na".to_string(), 4)));
+    }
+
+    #[test]
+    fn the_last_section_moves_without_a_final_newline() {
+        assert_eq!(section_moved(DOCUMENT, 4, 0, false), Some(("# C\nc\n# A\na\n# B\nb".to_string(), 0)));
+        assert_eq!(section_moved(DOCUMENT, 4, 2, false), Some(("# A\na\n# C\nc\n# B\nb".to_string(), 2)));
+    }
+
+    #[test]
+    fn sections_do_not_move_into_themselves() {
+        let text = "# A\n## A1\nx\n# B\nb";
+        assert_eq!(section_moved(text, 0, 1, false), None);
+        assert_eq!(section_moved(text, 0, 1, true), None);
+        assert_eq!(section_moved(text, 0, 0, true), None);
+        assert_eq!(section_moved(text, 0, 3, false), None);
+    }
+}
@@ -0,0 +1,280 @@
+use gtk4::prelude::*;
+use gtk4::gio::{self, Menu, SimpleAction};
+use gtk4::{
+    Align, Box as GtkBox, Button, CheckButton, DropDown, Entry, Grid, Label, Orientation, SpinButton, Window,
+};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::PoisonError;
+use std::time::Duration;
+
+use crate::config::{self, Config, ConfigPath, ConverterConfig, FontConfig};
+use crate::markdown::{IncrementalConverter, Options};
+use crate::theme::{user_themes, BUILTIN_THEMES};
+use crate::update::UPDATE_DELAY;
+use crate::MarkdownEditor;
+
+const PROFILES: [&str; 3] = ["entermd", "commonmark", "gfm"];
+
+// `selector { font-family: ..; font-size: .. }` for a pango font name like "Iosevka 13"
+fn font_css(selector: &str, font: &str) -> String {
+    let description = pango::FontDescription::from_string(font);
+    let mut rules = String::new();
+    if let Some(family) = description.family() {
+        rules.push_str(&format!(" font-family: \"{}\";", family));
+    }
+    if description.size() > 0 {
+        let unit = if description.is_size_absolute() { "px" } else { "pt" };
+        rules.push_str(&format!(" font-size: {}{};", description.size() as f64 / pango::SCALE as f64, unit));
+    }
+    format!("{} {{{} }}\n", selector, rules)
+}
+
+fn optional_text(entry: &Entry) -> Option<String> {
+    let text = entry.text().trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+impl MarkdownEditor {
+    /// Apply entermd.toml and open the preferences dialog from Ctrl+, or the right-click menu.
+    /// `overrides` are the command line's settings, which stay on top of whatever is saved.
+    pub(crate) fn connect_preferences(self: &Rc<Self>, config: &Config, overrides: Config) {
+        self.apply_config(config);
+
+        let preferences = SimpleAction::new("preferences", None);
+        let editor = self.clone();
+        preferences.connect_activate(move |_, _| editor.show_preferences(&overrides));
+        self.window.add_action(&preferences);
+        if let Some(app) = self.window.application() {
+            app.set_accels_for_action("win.preferences", &["<Control>comma"]);
+        }
+
+        let section = Menu::new();
+        section.append(Some("Preferences"), Some("win.preferences"));
+        self.context_menu.append_section(None, &section);
+    }
+
+    // everything but the converter options and output directory, which the command line may have set
+    fn apply_config(self: &Rc<Self>, config: &Config) {
+        *self.html_template.borrow_mut() = config.html_template();
+        self.update_delay.set(config.update_delay.map_or(UPDATE_DELAY, Duration::from_millis));
+
+        let theme = config.theme.clone().unwrap_or_else(|| "system".to_string());
+        if *self.theme.borrow() != theme {
+            if let Some(action) = self.window.lookup_action("theme") {
+                action.change_state(&theme.to_variant());
+            }
+            *self.theme.borrow_mut() = theme;
+            self.apply_theme();
+        }
+
+        let mut css = String::new();
+        if let Some(font) = &config.fonts.editor {
+            css.push_str(&font_css("textview#markdown_input", font));
+        }
+        if let Some(font) = &config.fonts.preview {
+            css.push_str(&font_css("textview#static_html, textview#rendered_preview", font));
+        }
+        self.font_provider.load_from_data(&css);
+
+        if let Some(app) = self.window.application() {
+            for (action, accels) in &config.keybindings {
+                if !action.contains('.') {
+                    eprintln!("entermd: keybinding for '{}': actions look like win.save", action);
+                    continue;
+                }
+                let accels: Vec<&str> = accels
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|accel| {
+                        let valid = gtk4::accelerator_parse(*accel).is_some();
+                        if !valid {
+                            eprintln!("entermd: keybinding for '{}': can't read '{}'", action, accel);
+                        }
+                        valid
+                    })
+                    .collect();
+                app.set_accels_for_action(action, &accels);
+            }
+        }
+
+        // the template may have changed under the HTML panes
+        self.update_all();
+    }
+
+    /// A dialog for the user-level entermd.toml, applied as soon as it's saved.
+    fn show_preferences(self: &Rc<Self>, overrides: &Config) {
+        let user = Config::load_user();
+        let options = user.options();
+
+        let dialog = Window::builder()
+            .title("Preferences")
+            .transient_for(&self.window)
+            .modal(true)
+            .default_width(480)
+            .build();
+        let grid = Grid::new();
+        grid.set_row_spacing(8);
+        grid.set_column_spacing(12);
+        grid.set_margin_top(16);
+        grid.set_margin_bottom(16);
+        grid.set_margin_start(16);
+        grid.set_margin_end(16);
+        let mut row = 0;
+        let mut add_row = |label: &str, widget: &gtk4::Widget| {
+            let label = Label::new(Some(label));
+            label.set_xalign(0.0);
+            grid.attach(&label, 0, row, 1, 1);
+            widget.set_hexpand(true);
+            grid.attach(widget, 1, row, 1, 1);
+            row += 1;
+        };
+
+        let template = Entry::new();
+        template.set_placeholder_text(Some("built-in"));
+        template.set_text(&user.template.as_ref().map(|path| path.written.display().to_string()).unwrap_or_default());
+        add_row("Page template", template.upcast_ref());
+
+        let output = Entry::new();
+        output.set_placeholder_text(Some("next to the markdown"));
+        output.set_text(&user.output.as_ref().map(|path| path.written.display().to_string()).unwrap_or_default());
+        add_row("Export directory", output.upcast_ref());
+
+        let profile = DropDown::from_strings(&PROFILES);
+        let current = user.converter.profile.as_deref().unwrap_or("entermd");
+        profile.set_selected(PROFILES.iter().position(|name| *name == current).unwrap_or(0) as u32);
+        add_row("Markdown flavour", profile.upcast_ref());
+
+        let extensions = GtkBox::new(Orientation::Vertical, 2);
+        let tables = CheckButton::with_label("Tables");
+        tables.set_active(options.tables);
+        let strikethrough = CheckButton::with_label("~~Strikethrough~~");
+        strikethrough.set_active(options.strikethrough);
+        let image_size = CheckButton::with_label("{width=.. height=..} image sizes");
+        image_size.set_active(options.image_size);
+        let dash_replacement = CheckButton::with_label("-- replacement");
+        dash_replacement.set_active(options.dash_replacement);
+        for check in [&tables, &strikethrough, &image_size, &dash_replacement] {
+            extensions.append(check);
+        }
+        add_row("Extensions", extensions.upcast_ref());
+
+        // settings names, and what the dropdown shows for them
+        let themes: Vec<String> = BUILTIN_THEMES.iter().map(|(_, name)| name.to_string()).chain(user_themes()).collect();
+        let theme_labels: Vec<&str> = BUILTIN_THEMES
+            .iter()
+            .map(|(label, _)| *label)
+            .chain(themes[BUILTIN_THEMES.len()..].iter().map(String::as_str))
+            .collect();
+        let theme = DropDown::from_strings(&theme_labels);
+        let current = user.theme.as_deref().unwrap_or("system");
+        let selected = themes.iter().position(|theme| theme == current).or_else(|| themes.iter().position(|theme| theme == "system"));
+        theme.set_selected(selected.unwrap_or(0) as u32);
+        add_row("Theme", theme.upcast_ref());
+
+        let editor_font = Entry::new();
+        editor_font.set_placeholder_text(Some("from the theme, or a font like \"Iosevka 13\""));
+        editor_font.set_text(user.fonts.editor.as_deref().unwrap_or(""));
+        add_row("Editor font", editor_font.upcast_ref());
+
+        let preview_font = Entry::new();
+        preview_font.set_placeholder_text(Some("from the theme"));
+        preview_font.set_text(user.fonts.preview.as_deref().unwrap_or(""));
+        add_row("Preview font", preview_font.upcast_ref());
+
+        let delay = SpinButton::with_range(0.0, 2000.0, 10.0);
+        delay.set_value(user.update_delay.unwrap_or(UPDATE_DELAY.as_millis() as u64) as f64);
+        delay.set_tooltip_text(Some("how long typing has to pause before the preview catches up"));
+        add_row("Update delay (ms)", delay.upcast_ref());
+
+        let note = Label::new(Some(&format!(
+            "Saved to {}. Keybindings are set in the file, and an entermd.toml in the project overrides these.",
+            config::user_path().display()
+        )));
+        note.set_wrap(true);
+        note.set_xalign(0.0);
+        note.add_css_class("dim-label");
+        grid.attach(&note, 0, row, 2, 1);
+
+        let open_file = Button::with_label("Open File");
+        let cancel = Button::with_label("Cancel");
+        let save = Button::with_label("Save");
+        save.add_css_class("suggested-action");
+        let buttons = GtkBox::new(Orientation::Horizontal, 8);
+        buttons.set_halign(Align::Fill);
+        open_file.set_hexpand(true);
+        open_file.set_halign(Align::Start);
+        buttons.append(&open_file);
+        buttons.append(&cancel);
+        buttons.append(&save);
+        grid.attach(&buttons, 0, row + 1, 2, 1);
+        dialog.set_child(Some(&grid));
+
+        let editor = self.clone();
+        open_file.connect_clicked(move |_| {
+            let path = config::user_path();
+            // there has to be a file to open
+            if !path.exists() {
+                if let Err(err) = Config::default().save_user() {
+                    editor.show_error("Couldn't create the config file", &err);
+                    return;
+                }
+            }
+            let uri = gio::File::for_path(&path).uri();
+            if let Err(err) = gio::AppInfo::launch_default_for_uri(&uri, None::<&gio::AppLaunchContext>) {
+                editor.show_error(&format!("Couldn't open {}", path.display()), &err.to_string());
+            }
+        });
+
+        let window = dialog.clone();
+        cancel.connect_clicked(move |_| window.close());
+
+        let editor = self.clone();
+        let window = dialog.clone();
+        let overrides = overrides.clone();
+        save.connect_clicked(move |_| {
+            let profile_name = PROFILES[profile.selected() as usize % PROFILES.len()];
+            let base = Options::from_profile(profile_name).unwrap_or_default();
+            // only what differs from the flavour goes in the file
+            let differs = |check: &CheckButton, default: bool| (check.is_active() != default).then_some(check.is_active());
+            let delay = delay.value() as u64;
+
+            let config = Config {
+                template: optional_text(&template).map(|path| ConfigPath::from(PathBuf::from(path))),
+                output: optional_text(&output).map(|path| ConfigPath::from(PathBuf::from(path))),
+                theme: Some(themes[theme.selected() as usize].clone()).filter(|name| name != "system"),
+                update_delay: (delay != UPDATE_DELAY.as_millis() as u64).then_some(delay),
+                converter: ConverterConfig {
+                    profile: (profile_name != "entermd").then(|| profile_name.to_string()),
+                    tables: differs(&tables, base.tables),
+                    strikethrough: differs(&strikethrough, base.strikethrough),
+                    image_size: differs(&image_size, base.image_size),
+                    dash_replacement: differs(&dash_replacement, base.dash_replacement),
+                },
+                fonts: FontConfig {
+                    editor: optional_text(&editor_font),
+                    preview: optional_text(&preview_font),
+                },
+                keybindings: user.keybindings.clone(),
+            };
+            if let Err(err) = config.save_user() {
+                editor.show_error("Couldn't save preferences", &err);
+                return;
+            }
+
+            // with the project's settings back on top, and the command line's over those
+            let config = Config::load().merge(overrides.clone());
+            let options = config.options();
+            editor.options.set(options);
+            for doc in editor.documents() {
+                *doc.converter.lock().unwrap_or_else(PoisonError::into_inner) = IncrementalConverter::new(options);
+                doc.converter.clear_poison();
+            }
+            *editor.export_dir.borrow_mut() = config.output.as_ref().map(ConfigPath::resolved);
+            editor.apply_config(&config);
+            window.close();
+        });
+
+        dialog.present();
+    }
+}
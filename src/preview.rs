@@ -0,0 +1,352 @@
+use gtk4::prelude::*;
+use gtk4::{gdk, gdk_pixbuf, TextBuffer, TextTag, TextView, WrapMode};
+use glib::idle_add_local_once;
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// A read-only view showing the page in `html_buffer` rendered, kept in step with it.
+///
+/// Relative image paths are looked up next to `current_file`.
+pub fn rendered_view(html_buffer: &TextBuffer, current_file: Rc<RefCell<Option<PathBuf>>>) -> TextView {
+    let view = TextView::new();
+    view.set_widget_name("rendered_preview");
+    view.set_editable(false);
+    view.set_cursor_visible(false);
+    view.set_wrap_mode(WrapMode::WordChar);
+    view.set_left_margin(10);
+    view.set_right_margin(10);
+    view.set_top_margin(10);
+    view.set_bottom_margin(10);
+    create_tags(&view.buffer());
+
+    // set_text changes the buffer twice (clear, then insert), so render once both are done
+    let scheduled = Rc::new(Cell::new(false));
+    let render_later = {
+        let view = view.clone();
+        let html_buffer = html_buffer.clone();
+        move || {
+            if scheduled.replace(true) {
+                return;
+            }
+            let view = view.clone();
+            let html_buffer = html_buffer.clone();
+            let scheduled = scheduled.clone();
+            let current_file = current_file.clone();
+            idle_add_local_once(move || {
+                scheduled.set(false);
+                // nobody's looking, catch up when it's shown again
+                if !view.is_mapped() {
+                    return;
+                }
+                let html = html_buffer.text(&html_buffer.start_iter(), &html_buffer.end_iter(), false);
+                let base_dir = current_file.borrow().as_ref().and_then(|path| path.parent().map(Path::to_path_buf));
+                render(&view.buffer(), &html, base_dir.as_deref());
+            });
+        }
+    };
+    let render_changed = render_later.clone();
+    html_buffer.connect_changed(move |_| render_changed());
+    view.connect_map(move |_| render_later());
+
+    view
+}
+
+fn create_tags(buffer: &TextBuffer) {
+    let table = buffer.tag_table();
+    let scales = [
+        pango::SCALE_XX_LARGE,
+        pango::SCALE_X_LARGE,
+        pango::SCALE_LARGE,
+        pango::SCALE_MEDIUM,
+        pango::SCALE_SMALL,
+        pango::SCALE_X_SMALL,
+    ];
+    for (i, scale) in scales.into_iter().enumerate() {
+        table.add(&TextTag::builder()
+            .name(format!("h{}", i + 1))
+            .scale(scale)
+            .weight(700)
+            .pixels_above_lines(12)
+            .pixels_below_lines(4)
+            .build());
+    }
+    table.add(&TextTag::builder().name("strong").weight(700).build());
+    table.add(&TextTag::builder().name("em").style(pango::Style::Italic).build());
+    table.add(&TextTag::builder().name("s").strikethrough(true).build());
+    table.add(&TextTag::builder()
+        .name("code")
+        .family("monospace")
+        .background("rgba(127, 127, 127, 0.2)")
+        .build());
+    table.add(&TextTag::builder()
+        .name("pre")
+        .family("monospace")
+        .paragraph_background("rgba(127, 127, 127, 0.15)")
+        .left_margin(20)
+        .build());
+    table.add(&TextTag::builder()
+        .name("a")
+        .foreground("#3a7bd5")
+        .underline(pango::Underline::Single)
+        .build());
+    table.add(&TextTag::builder().name("th").weight(700).build());
+
+    let mut tabs = pango::TabArray::new(8, true);
+    for i in 0..8 {
+        tabs.set_tab(i, pango::TabAlign::Left, 160 * (i + 1));
+    }
+    table.add(&TextTag::builder().name("table").tabs(&tabs).build());
+    table.add(&TextTag::builder()
+        .name("hr")
+        .foreground("rgba(127, 127, 127, 0.6)")
+        .justification(gtk4::Justification::Center)
+        .build());
+    table.add(&TextTag::builder().name("image").style(pango::Style::Italic).foreground("rgba(127, 127, 127, 0.9)").build());
+}
+
+// the inline tags we pass straight through to TextTags of the same name
+const INLINE_TAGS: [&str; 5] = ["strong", "em", "s", "code", "a"];
+
+/// Render the `<body>` of a page produced by the converter.
+///
+/// This only knows the handful of tags `markdown_to_html` emits, anything
+/// else is dropped and its text kept.
+fn render(buffer: &TextBuffer, html: &str, base_dir: Option<&Path>) {
+    buffer.set_text("");
+    let body = match (html.find("<body>"), html.rfind("</body>")) {
+        (Some(start), Some(end)) if start < end => &html[start + "<body>".len()..end],
+        _ => html,
+    };
+
+    let mut renderer = Renderer {
+        buffer,
+        base_dir,
+        tags: Vec::new(),
+        list_depth: 0,
+        in_block: 0,
+        in_pre: false,
+        first_cell: false,
+    };
+    let mut rest = body;
+    while !rest.is_empty() {
+        match rest.find('<') {
+            Some(0) => {
+                let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+                renderer.tag(&rest[1..end.saturating_sub(1).max(1)]);
+                rest = &rest[end..];
+            }
+            Some(start) => {
+                renderer.text(&rest[..start]);
+                rest = &rest[start..];
+            }
+            None => {
+                renderer.text(rest);
+                rest = "";
+            }
+        }
+    }
+}
+
+struct Renderer<'a> {
+    buffer: &'a TextBuffer,
+    base_dir: Option<&'a Path>,
+    // TextTags applied to text right now, innermost last
+    tags: Vec<&'static str>,
+    list_depth: usize,
+    // inside something that holds text (paragraph, heading, list item, table cell)
+    in_block: usize,
+    in_pre: bool,
+    first_cell: bool,
+}
+
+impl Renderer<'_> {
+    fn tag(&mut self, tag: &str) {
+        let (closing, tag) = match tag.strip_prefix('/') {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+        let name = tag.split_whitespace().next().unwrap_or("").to_ascii_lowercase();
+
+        match (name.as_str(), closing) {
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", false) => {
+                self.start_block();
+                self.tags.push(["h1", "h2", "h3", "h4", "h5", "h6"][name.as_bytes()[1] as usize - b'1' as usize]);
+                self.in_block += 1;
+            }
+            ("p", false) => {
+                self.start_block();
+                self.in_block += 1;
+            }
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "p", true) => {
+                if name != "p" {
+                    self.tags.pop();
+                }
+                self.in_block = self.in_block.saturating_sub(1);
+                self.end_block();
+            }
+            ("ul", false) => {
+                if self.list_depth == 0 {
+                    self.start_block();
+                }
+                self.list_depth += 1;
+            }
+            ("ul", true) => {
+                self.list_depth = self.list_depth.saturating_sub(1);
+                if self.list_depth == 0 {
+                    self.end_block();
+                }
+            }
+            ("li", false) => {
+                self.new_line();
+                let bullet = format!("{}• ", "    ".repeat(self.list_depth.saturating_sub(1)));
+                self.insert(&bullet);
+                self.in_block += 1;
+            }
+            ("li", true) => self.in_block = self.in_block.saturating_sub(1),
+            ("pre", false) => {
+                self.start_block();
+                self.tags.push("pre");
+                self.in_pre = true;
+            }
+            ("pre", true) => {
+                self.tags.pop();
+                self.in_pre = false;
+                self.end_block();
+            }
+            // inside <pre> the code tag only marks the block
+            ("code", _) if self.in_pre => {}
+            ("table", false) => {
+                self.start_block();
+                self.tags.push("table");
+            }
+            ("table", true) => {
+                self.tags.pop();
+                self.end_block();
+            }
+            ("tr", false) => {
+                self.new_line();
+                self.first_cell = true;
+            }
+            ("th" | "td", false) => {
+                if !self.first_cell {
+                    self.insert("\t");
+                }
+                self.first_cell = false;
+                if name == "th" {
+                    self.tags.push("th");
+                }
+                self.in_block += 1;
+            }
+            ("th" | "td", true) => {
+                if name == "th" {
+                    self.tags.pop();
+                }
+                self.in_block = self.in_block.saturating_sub(1);
+            }
+            ("hr", false) => {
+                self.start_block();
+                self.tags.push("hr");
+                self.insert(&"─".repeat(24));
+                self.tags.pop();
+                self.end_block();
+            }
+            ("br", false) => self.insert("\n"),
+            ("img", false) => self.image(tag),
+            (inline, false) if INLINE_TAGS.contains(&inline) => {
+                self.tags.push(INLINE_TAGS.into_iter().find(|known| *known == inline).unwrap());
+            }
+            (inline, true) if INLINE_TAGS.contains(&inline) => {
+                if let Some(i) = self.tags.iter().rposition(|open| *open == inline) {
+                    self.tags.remove(i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let text = decode_entities(text);
+        if self.in_pre {
+            self.insert(&text);
+            return;
+        }
+        // the converter indents and line-breaks its output, none of that is content
+        if self.in_block == 0 && text.trim().is_empty() {
+            return;
+        }
+        let mut collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.starts_with(char::is_whitespace) && !self.at_line_start() {
+            collapsed.insert(0, ' ');
+        }
+        if text.ends_with(char::is_whitespace) && !collapsed.is_empty() && !collapsed.ends_with(' ') {
+            collapsed.push(' ');
+        }
+        self.insert(&collapsed);
+    }
+
+    fn image(&mut self, tag: &str) {
+        let src = attribute(tag, "src").unwrap_or_default();
+        let alt = attribute(tag, "alt").unwrap_or_default();
+        let width = attribute(tag, "width").and_then(|w| w.parse().ok()).unwrap_or(-1);
+        let height = attribute(tag, "height").and_then(|h| h.parse().ok()).unwrap_or(-1);
+
+        let path = match self.base_dir {
+            Some(dir) => dir.join(&src),
+            None => PathBuf::from(&src),
+        };
+        match gdk_pixbuf::Pixbuf::from_file_at_scale(&path, width, height, true) {
+            Ok(pixbuf) => {
+                let texture = gdk::Texture::for_pixbuf(&pixbuf);
+                self.buffer.insert_paintable(&mut self.buffer.end_iter(), &texture);
+            }
+            // remote or missing images get a placeholder
+            Err(_) => {
+                self.tags.push("image");
+                self.insert(&format!("[image: {}]", if alt.is_empty() { &src } else { &alt }));
+                self.tags.pop();
+            }
+        }
+    }
+
+    fn insert(&self, text: &str) {
+        self.buffer.insert_with_tags_by_name(&mut self.buffer.end_iter(), text, &self.tags);
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.buffer.end_iter().starts_line()
+    }
+
+    fn new_line(&self) {
+        if !self.at_line_start() {
+            self.insert("\n");
+        }
+    }
+
+    // blocks are separated by an empty line
+    fn start_block(&self) {
+        if self.buffer.char_count() > 0 {
+            self.new_line();
+        }
+    }
+
+    fn end_block(&self) {
+        self.new_line();
+        self.buffer.insert(&mut self.buffer.end_iter(), "\n");
+    }
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!("{}=\"", name))? + name.len() + 2;
+    let end = tag[start..].find('"')? + start;
+    Some(decode_entities(&tag[start..end]))
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,304 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::site::BuildReport;
+
+// browsers listen here for reloads
+const EVENTS_PATH: &str = "/__entermd/events";
+// where the editor's buffer is served, one level down like the site's pages
+pub const LIVE_PAGE_PATH: &str = "/preview.html";
+
+// each page says which it is, so only pages that changed are refreshed; the path is already url-encoded
+const RELOAD_SCRIPT: &str = "<script>new EventSource(\"/__entermd/events?page=\" + location.pathname).onmessage = () => location.reload();</script>\n";
+
+/// Serves a directory on localhost, refreshing open pages when they change.
+#[derive(Clone)]
+pub struct PreviewServer {
+    shared: Arc<Shared>,
+    pub port: u16,
+}
+
+struct Shared {
+    root: PathBuf,
+    // open event streams and the page each is for, dropped once the browser goes away
+    clients: Mutex<Vec<(String, TcpStream)>>,
+    // the editor's rendered buffer, if there is one
+    live_page: Mutex<Option<String>>,
+}
+
+impl PreviewServer {
+    /// Start serving `root` on 127.0.0.1:`port` in the background.
+    pub fn start(root: &Path, port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let server = PreviewServer {
+            shared: Arc::new(Shared {
+                root: root.to_path_buf(),
+                clients: Mutex::new(Vec::new()),
+                live_page: Mutex::new(None),
+            }),
+            port: listener.local_addr()?.port(),
+        };
+
+        let shared = server.shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    // a browser hanging up mid-response is nothing to worry about
+                    let _ = handle_connection(&shared, stream);
+                });
+            }
+        });
+
+        Ok(server)
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}/", self.port)
+    }
+
+    /// Tell every open page to refresh.
+    pub fn reload(&self) {
+        self.reload_where(|_| true);
+    }
+
+    /// Refresh the pages a site build rendered, or every page if css, images or
+    /// anything else they might use changed.
+    pub fn reload_built(&self, report: &BuildReport) {
+        if !(report.copied.is_empty() && report.removed.is_empty()) {
+            return self.reload();
+        }
+        let pages: HashSet<String> = report.rendered.iter()
+            .map(|rel| format!("/{}", rel.with_extension("html").to_string_lossy().replace('\\', "/")))
+            .collect();
+        self.reload_where(|page| pages.contains(page));
+    }
+
+    /// Replace the page served at [`LIVE_PAGE_PATH`], refreshing it if it changed.
+    pub fn set_live_page(&self, html: &str) {
+        let mut live_page = self.shared.live_page.lock().unwrap();
+        if live_page.as_deref() == Some(html) {
+            return;
+        }
+        *live_page = Some(html.to_string());
+        drop(live_page);
+        self.reload_where(|page| page == LIVE_PAGE_PATH);
+    }
+
+    fn reload_where(&self, changed: impl Fn(&str) -> bool) {
+        let mut clients = self.shared.clients.lock().unwrap();
+        clients.retain_mut(|(page, client)| !changed(page) || client.write_all(b"data: reload\n\n").is_ok());
+    }
+}
+
+fn handle_connection(shared: &Shared, stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // skip the headers, nothing in them matters here
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let mut stream = Response { stream, head: false };
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return stream.send("400 Bad Request", "text/plain", b"bad request");
+    };
+    if method != "GET" && method != "HEAD" {
+        return stream.send("405 Method Not Allowed", "text/plain", b"method not allowed");
+    }
+    // HEAD gets the same headers as GET, without the body
+    stream.head = method == "HEAD";
+    let target = target.split('#').next().unwrap_or("/");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = percent_decode(path);
+
+    if path == EVENTS_PATH && !stream.head {
+        stream.stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n")?;
+        // the page is all of the query, paths can have `&` in them
+        let page = query.strip_prefix("page=").map_or_else(String::new, page_path);
+        shared.clients.lock().unwrap().push((page, stream.stream));
+        return Ok(());
+    }
+
+    if path == LIVE_PAGE_PATH {
+        if let Some(html) = shared.live_page.lock().unwrap().clone() {
+            return stream.send("200 OK", "text/html; charset=utf-8", inject_reload_script(&html).as_bytes());
+        }
+    }
+
+    let Some(file) = resolve(&shared.root, &path) else {
+        return stream.send("404 Not Found", "text/plain", b"not found");
+    };
+    let content_type = content_type(&file);
+    match fs::read(&file) {
+        Ok(body) if content_type.starts_with("text/html") => {
+            let html = inject_reload_script(&String::from_utf8_lossy(&body));
+            stream.send("200 OK", content_type, html.as_bytes())
+        }
+        Ok(body) => stream.send("200 OK", content_type, &body),
+        Err(_) => stream.send("404 Not Found", "text/plain", b"not found"),
+    }
+}
+
+struct Response {
+    stream: TcpStream,
+    head: bool,
+}
+
+impl Response {
+    fn send(&mut self, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+        write!(
+            self.stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+            status, content_type, body.len()
+        )?;
+        if self.head {
+            return Ok(());
+        }
+        self.stream.write_all(body)
+    }
+}
+
+// the page a browser says it's on, the way `reload_built` names them: `/blog/` is `/blog/index.html`
+fn page_path(encoded: &str) -> String {
+    let page = percent_decode(encoded);
+    if page.ends_with('/') {
+        page + "index.html"
+    } else {
+        page
+    }
+}
+
+// map a url path onto the served directory, refusing anything that climbs out of it
+fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let rel = Path::new(path.trim_start_matches('/'));
+    if rel.components().any(|part| !matches!(part, Component::Normal(_))) {
+        return None;
+    }
+    let file = root.join(rel);
+    if file.is_dir() {
+        Some(file.join("index.html"))
+    } else {
+        Some(file)
+    }
+}
+
+fn inject_reload_script(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(end) => format!("{}{}{}", &html[..end], RELOAD_SCRIPT, &html[end..]),
+        None => format!("{}{}", html, RELOAD_SCRIPT),
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()).unwrap_or("") {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::io::Read;
+    use std::time::Duration;
+
+    fn request(server: &PreviewServer, request: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+        stream.write_all(format!("{}\r\nHost: localhost\r\n\r\n", request).as_bytes()).unwrap();
+        stream
+    }
+
+    fn read_all(mut stream: TcpStream) -> String {
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn head_has_no_body() {
+        let dir = TempDir::new("serve-head");
+        dir.write("index.html", "<body>hi</body>");
+        let server = PreviewServer::start(dir.path(), 0).unwrap();
+        let get = read_all(request(&server, "GET / HTTP/1.1"));
+        let head = read_all(request(&server, "HEAD / HTTP/1.1"));
+
+        assert!(get.ends_with("</body>"));
+        let length = get.lines().find(|line| line.starts_with("Content-Length")).unwrap();
+        assert!(head.contains(length));
+        assert!(head.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn only_changed_pages_reload() {
+        let dir = TempDir::new("serve-reload");
+        let server = PreviewServer::start(dir.path(), 0).unwrap();
+        let mut live = request(&server, "GET /__entermd/events?page=/preview.html HTTP/1.1");
+        let mut other = request(&server, "GET /__entermd/events?page=/blog/ HTTP/1.1");
+        let mut spaced = request(&server, "GET /__entermd/events?page=/my%20notes&more.html HTTP/1.1");
+        for stream in [&mut live, &mut other, &mut spaced] {
+            stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+            let mut headers = [0; 256];
+            let _ = stream.read(&mut headers).unwrap();
+        }
+        // all the streams registered
+        while server.shared.clients.lock().unwrap().len() < 3 {
+            thread::yield_now();
+        }
+
+        server.set_live_page("<p>new</p>");
+        let mut event = [0; 64];
+        let read = live.read(&mut event).unwrap();
+        assert_eq!(&event[..read], b"data: reload\n\n");
+        assert!(other.read(&mut event).is_err());
+        assert!(spaced.read(&mut event).is_err());
+        assert!(server.shared.clients.lock().unwrap().iter().any(|(page, _)| page == "/blog/index.html"));
+
+        let report = BuildReport { rendered: vec![PathBuf::from("my notes&more.md")], ..BuildReport::default() };
+        server.reload_built(&report);
+        let read = spaced.read(&mut event).unwrap();
+        assert_eq!(&event[..read], b"data: reload\n\n");
+        assert!(other.read(&mut event).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use glib::{KeyFile, KeyFileFlags};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::layout::Layout;
+
+/// Where entermd keeps its settings and themes.
+pub fn config_dir() -> PathBuf {
+    glib::user_config_dir().join("entermd")
+}
+
+/// Window state kept between runs, in `~/.config/entermd/settings.ini`. Actual
+/// settings, the theme included, are in entermd.toml.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    pub window: WindowState,
+}
+
+/// How the window looked when it was last closed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowState {
+    pub width: i32,
+    pub height: i32,
+    pub maximized: bool,
+    pub fullscreen: bool,
+    /// where the divider between the panes was, unset until the window has been closed once
+    pub pane_position: Option<i32>,
+    pub layout: Layout,
+    /// whether the project sidebar is showing
+    pub sidebar: bool,
+    /// how wide the sidebar was, unset until the window has been closed once
+    pub sidebar_position: Option<i32>,
+    /// whether the outline under the project files is open
+    pub outline: bool,
+}
+
+impl Default for WindowState {
+    fn default() -> Self {
+        WindowState {
+            width: 800,
+            height: 600,
+            maximized: false,
+            fullscreen: false,
+            pane_position: None,
+            layout: Layout::Horizontal,
+            sidebar: true,
+            sidebar_position: None,
+            outline: true,
+        }
+    }
+}
+
+impl Settings {
+    pub fn path() -> PathBuf {
+        config_dir().join("settings.ini")
+    }
+
+    /// The saved settings, with defaults for anything missing or unreadable.
+    pub fn load() -> Self {
+        let file = KeyFile::new();
+        let mut settings = Settings::default();
+        if file.load_from_file(Self::path(), KeyFileFlags::NONE).is_err() {
+            return settings;
+        }
+        let window = &mut settings.window;
+        // a size too small to find the window again is as good as none
+        if let (Ok(width), Ok(height)) = (file.integer("window", "width"), file.integer("window", "height")) {
+            if width >= 200 && height >= 150 {
+                window.width = width;
+                window.height = height;
+            }
+        }
+        window.maximized = file.boolean("window", "maximized").unwrap_or(false);
+        window.fullscreen = file.boolean("window", "fullscreen").unwrap_or(false);
+        window.pane_position = file.integer("window", "pane-position").ok().filter(|&position| position >= 0);
+        if let Some(layout) = file.string("window", "layout").ok().and_then(|name| Layout::from_name(&name)) {
+            window.layout = layout;
+        }
+        window.sidebar = file.boolean("window", "sidebar").unwrap_or(true);
+        window.sidebar_position = file.integer("window", "sidebar-position").ok().filter(|&position| position >= 0);
+        window.outline = file.boolean("window", "outline").unwrap_or(true);
+        settings
+    }
+
+    /// Write the settings back, keeping anything else in the file (comments included).
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path();
+        let file = KeyFile::new();
+        // a missing file just means there's nothing to keep
+        let _ = file.load_from_file(&path, KeyFileFlags::KEEP_COMMENTS);
+        // from before the theme moved to entermd.toml
+        let _ = file.remove_group("appearance");
+        file.set_integer("window", "width", self.window.width);
+        file.set_integer("window", "height", self.window.height);
+        file.set_boolean("window", "maximized", self.window.maximized);
+        file.set_boolean("window", "fullscreen", self.window.fullscreen);
+        if let Some(position) = self.window.pane_position {
+            file.set_integer("window", "pane-position", position);
+        }
+        file.set_string("window", "layout", self.window.layout.name());
+        file.set_boolean("window", "sidebar", self.window.sidebar);
+        if let Some(position) = self.window.sidebar_position {
+            file.set_integer("window", "sidebar-position", position);
+        }
+        file.set_boolean("window", "outline", self.window.outline);
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+        }
+        file.save_to_file(&path).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+}
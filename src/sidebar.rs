@@ -0,0 +1,572 @@
+use gtk4::prelude::*;
+use gtk4::gdk::Key;
+use gtk4::gio::{self, FileMonitorEvent, FileMonitorFlags, Menu, SimpleAction};
+use gtk4::{
+    Box as GtkBox, ButtonsType, CellRendererPixbuf, CellRendererText, Entry, EventControllerKey, FileChooserAction,
+    FileChooserDialog, GestureClick, Label, MenuButton, MessageDialog, MessageType, Orientation, PolicyType,
+    ResponseType, ScrolledWindow, TreeIter, TreeStore, TreeView, TreeViewColumn,
+};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::MarkdownEditor;
+
+// columns of the project tree
+const NAME: u32 = 0;
+const PATH: u32 = 1;
+const IS_DIR: u32 = 2;
+const ICON: u32 = 3;
+// `●` for unsaved changes, `⚠` for a conversion that failed
+const STATUS: u32 = 4;
+const TOOLTIP: u32 = 5;
+
+// a save or a checkout touches several files, refresh once they're all done
+const REFRESH_DELAY: Duration = Duration::from_millis(200);
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "md" || ext == "markdown")
+}
+
+/// The project tree, with a header holding the project's name and its file actions.
+pub fn project_sidebar() -> (GtkBox, TreeView, Label, MenuButton) {
+    let store = TreeStore::new(&[
+        String::static_type(),
+        String::static_type(),
+        bool::static_type(),
+        gio::Icon::static_type(),
+        String::static_type(),
+        String::static_type(),
+    ]);
+    let view = TreeView::with_model(&store);
+    view.set_widget_name("project_tree");
+    view.set_headers_visible(false);
+    view.set_activate_on_single_click(true);
+    view.set_search_column(NAME as i32);
+    view.set_tooltip_column(TOOLTIP as i32);
+
+    let column = TreeViewColumn::new();
+    let icon = CellRendererPixbuf::new();
+    column.pack_start(&icon, false);
+    column.add_attribute(&icon, "gicon", ICON as i32);
+    let name = CellRendererText::new();
+    column.pack_start(&name, true);
+    column.add_attribute(&name, "text", NAME as i32);
+    let status = CellRendererText::new();
+    column.pack_end(&status, false);
+    column.add_attribute(&status, "text", STATUS as i32);
+    view.append_column(&column);
+
+    let title = Label::new(None);
+    title.set_hexpand(true);
+    title.set_xalign(0.0);
+    title.set_ellipsize(pango::EllipsizeMode::Middle);
+    let menu_button = MenuButton::new();
+    menu_button.set_icon_name("view-more-symbolic");
+    menu_button.set_has_frame(false);
+    menu_button.set_tooltip_text(Some("File actions"));
+    let header = GtkBox::new(Orientation::Horizontal, 4);
+    header.set_widget_name("project_header");
+    header.append(&title);
+    header.append(&menu_button);
+
+    let scrolled = ScrolledWindow::new();
+    scrolled.set_policy(PolicyType::Never, PolicyType::Automatic);
+    scrolled.set_vexpand(true);
+    scrolled.set_child(Some(&view));
+
+    let sidebar = GtkBox::new(Orientation::Vertical, 0);
+    sidebar.set_widget_name("project_sidebar");
+    sidebar.append(&header);
+    sidebar.append(&scrolled);
+    (sidebar, view, title, menu_button)
+}
+
+impl MarkdownEditor {
+    /// List `project` in the sidebar, keep it up to date as files come and go, and
+    /// create, rename and delete files from its menu (also F2 and Delete).
+    pub(crate) fn connect_sidebar(self: &Rc<Self>, project: PathBuf) {
+        // folders are listed the first time they're opened
+        let editor = self.clone();
+        self.project_view.connect_test_expand_row(move |_, iter, _| {
+            let store = &editor.project_store;
+            if let Some(child) = store.iter_children(Some(iter)) {
+                if store.get::<String>(&child, PATH as i32).is_empty() {
+                    store.remove(&child);
+                    let dir = PathBuf::from(store.get::<String>(iter, PATH as i32));
+                    editor.fill_project(&dir, Some(iter));
+                    editor.update_file_status();
+                }
+            }
+            glib::Propagation::Proceed
+        });
+
+        let editor = self.clone();
+        self.project_view.connect_row_activated(move |view, path, _| {
+            let Some(iter) = editor.project_store.iter(path) else {
+                return;
+            };
+            if editor.project_store.get::<bool>(&iter, IS_DIR as i32) {
+                if view.row_expanded(path) {
+                    view.collapse_row(path);
+                } else {
+                    view.expand_row(path, false);
+                }
+                return;
+            }
+            let file = editor.project_store.get::<String>(&iter, PATH as i32);
+            if file.is_empty() {
+                return;
+            }
+            let file = PathBuf::from(file);
+            if is_markdown(&file) {
+                editor.open_path(&file);
+                return;
+            }
+            // templates, styles and images go to whatever the desktop opens them with
+            let uri = gio::File::for_path(&file).uri();
+            if let Err(err) = gio::AppInfo::launch_default_for_uri(&uri, None::<&gio::AppLaunchContext>) {
+                editor.show_error(&format!("Couldn't open {}", file.display()), &err.to_string());
+            }
+        });
+
+        let new_file = SimpleAction::new("project-new-file", None);
+        let editor = self.clone();
+        new_file.connect_activate(move |_, _| editor.create_in_project(false));
+        self.window.add_action(&new_file);
+
+        let new_folder = SimpleAction::new("project-new-folder", None);
+        let editor = self.clone();
+        new_folder.connect_activate(move |_, _| editor.create_in_project(true));
+        self.window.add_action(&new_folder);
+
+        let rename = SimpleAction::new("project-rename", None);
+        let editor = self.clone();
+        rename.connect_activate(move |_, _| editor.rename_selected());
+        self.window.add_action(&rename);
+
+        let delete = SimpleAction::new("project-delete", None);
+        let editor = self.clone();
+        delete.connect_activate(move |_, _| editor.delete_selected());
+        self.window.add_action(&delete);
+
+        let open_folder = SimpleAction::new("open-folder", None);
+        let editor = self.clone();
+        open_folder.connect_activate(move |_, _| editor.choose_project());
+        self.window.add_action(&open_folder);
+
+        let visible = self.settings.borrow().window.sidebar;
+        let show = SimpleAction::new_stateful("sidebar", None, &visible.to_variant());
+        let editor = self.clone();
+        show.connect_activate(move |action, _| {
+            let visible = !editor.settings.borrow().window.sidebar;
+            editor.settings.borrow_mut().window.sidebar = visible;
+            action.set_state(&visible.to_variant());
+            if let Some(sidebar) = editor.sidebar.start_child() {
+                sidebar.set_visible(visible);
+            }
+        });
+        self.window.add_action(&show);
+        if let Some(sidebar) = self.sidebar.start_child() {
+            sidebar.set_visible(visible);
+        }
+        if let Some(position) = self.settings.borrow().window.sidebar_position {
+            self.sidebar.set_position(position);
+        }
+
+        if let Some(app) = self.window.application() {
+            app.set_accels_for_action("win.sidebar", &["F9"]);
+        }
+
+        let menu = Menu::new();
+        let create = Menu::new();
+        create.append(Some("New File"), Some("win.project-new-file"));
+        create.append(Some("New Folder"), Some("win.project-new-folder"));
+        menu.append_section(None, &create);
+        let change = Menu::new();
+        change.append(Some("Rename"), Some("win.project-rename"));
+        change.append(Some("Move to Trash"), Some("win.project-delete"));
+        menu.append_section(None, &change);
+        let folder = Menu::new();
+        folder.append(Some("Open Folder"), Some("win.open-folder"));
+        menu.append_section(None, &folder);
+        self.project_menu.set_menu_model(Some(&menu));
+
+        // right-clicking a row picks it and opens the same menu
+        let right_click = GestureClick::new();
+        right_click.set_button(3);
+        let editor = self.clone();
+        right_click.connect_pressed(move |_, _, x, y| {
+            let view = &editor.project_view;
+            let (x, y) = view.convert_widget_to_bin_window_coords(x as i32, y as i32);
+            match view.path_at_pos(x, y) {
+                Some((Some(path), _, _, _)) => view.selection().select_path(&path),
+                _ => view.selection().unselect_all(),
+            }
+            editor.project_menu.popup();
+        });
+        self.project_view.add_controller(right_click);
+
+        let keys = EventControllerKey::new();
+        let window = self.window.clone();
+        keys.connect_key_pressed(move |_, key, _, _| {
+            let action = match key {
+                Key::F2 => "project-rename",
+                Key::Delete | Key::KP_Delete => "project-delete",
+                _ => return glib::Propagation::Proceed,
+            };
+            ActionGroupExt::activate_action(&window, action, None);
+            glib::Propagation::Stop
+        });
+        self.project_view.add_controller(keys);
+
+        // with the sidebar toggle next to the layouts
+        let section = Menu::new();
+        section.append(Some("Show Files"), Some("win.sidebar"));
+        self.context_menu.append_section(None, &section);
+
+        self.open_project(project);
+    }
+
+    /// Show `dir` in the sidebar.
+    pub(crate) fn open_project(self: &Rc<Self>, dir: PathBuf) {
+        // open documents have absolute paths, rows have to match them
+        let dir = fs::canonicalize(&dir).unwrap_or(dir);
+        let name = dir.file_name().map_or_else(|| dir.display().to_string(), |name| name.to_string_lossy().into_owned());
+        self.project_title.set_text(&name);
+        self.project_title.set_tooltip_text(Some(&dir.display().to_string()));
+        *self.project_dir.borrow_mut() = Some(dir);
+        self.refresh_project();
+    }
+
+    fn choose_project(self: &Rc<Self>) {
+        let dialog = FileChooserDialog::new(
+            Some("Open Folder"),
+            Some(&self.window),
+            FileChooserAction::SelectFolder,
+            &[("_Cancel", ResponseType::Cancel), ("_Open", ResponseType::Accept)],
+        );
+        dialog.set_modal(true);
+        if let Some(dir) = self.project_dir.borrow().as_ref() {
+            let _ = dialog.set_current_folder(Some(&gio::File::for_path(dir)));
+        }
+        let editor = self.clone();
+        dialog.connect_response(move |dialog, response| {
+            let path = dialog.file().and_then(|file| file.path());
+            dialog.close();
+            if let (ResponseType::Accept, Some(path)) = (response, path) {
+                editor.open_project(path);
+            }
+        });
+        dialog.present();
+    }
+
+    // list the project again, keeping the same folders open
+    fn refresh_project(self: &Rc<Self>) {
+        let Some(dir) = self.project_dir.borrow().clone() else {
+            return;
+        };
+        let mut expanded = HashSet::new();
+        let store = self.project_store.clone();
+        self.project_view.map_expanded_rows(|_, path| {
+            if let Some(iter) = store.iter(path) {
+                expanded.insert(store.get::<String>(&iter, PATH as i32));
+            }
+        });
+
+        self.project_store.clear();
+        self.project_monitors.borrow_mut().clear();
+        self.fill_project(&dir, None);
+        self.expand_project_rows(None, &expanded);
+        self.update_file_status();
+    }
+
+    // open the rows under `parent` that were open before, which lists what's in them
+    fn expand_project_rows(&self, parent: Option<&TreeIter>, expanded: &HashSet<String>) {
+        let Some(iter) = self.project_store.iter_children(parent) else {
+            return;
+        };
+        loop {
+            if expanded.contains(&self.project_store.get::<String>(&iter, PATH as i32)) {
+                self.project_view.expand_row(&self.project_store.path(&iter), false);
+                self.expand_project_rows(Some(&iter), expanded);
+            }
+            if !self.project_store.iter_next(&iter) {
+                break;
+            }
+        }
+    }
+
+    // one level of `dir`; folders get a placeholder row until they're opened, so a
+    // big directory costs nothing until someone looks inside it
+    fn fill_project(self: &Rc<Self>, dir: &Path, parent: Option<&TreeIter>) {
+        match gio::File::for_path(dir).monitor_directory(FileMonitorFlags::WATCH_MOVES, None::<&gio::Cancellable>) {
+            Ok(monitor) => {
+                let editor = self.clone();
+                monitor.connect_changed(move |_, _, _, event| {
+                    // files being written to don't change the tree, only files coming and going do
+                    if matches!(
+                        event,
+                        FileMonitorEvent::Created
+                            | FileMonitorEvent::Deleted
+                            | FileMonitorEvent::Moved
+                            | FileMonitorEvent::MovedIn
+                            | FileMonitorEvent::MovedOut
+                            | FileMonitorEvent::Renamed
+                    ) {
+                        editor.schedule_project_refresh();
+                    }
+                });
+                self.project_monitors.borrow_mut().push(monitor);
+            }
+            Err(err) => eprintln!("entermd: couldn't watch {}: {}", dir.display(), err),
+        }
+
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        // exports landing inside the project aren't part of it
+        let skip = self.export_dir.borrow().as_ref().and_then(|dir| fs::canonicalize(dir).ok());
+        let mut entries: Vec<(bool, String, PathBuf)> = entries
+            .flatten()
+            .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+            .map(|entry| (!entry.path().is_dir(), entry.file_name().to_string_lossy().into_owned(), entry.path()))
+            .filter(|(_, _, path)| skip.is_none() || fs::canonicalize(path).ok() != skip)
+            .collect();
+        // folders first, then by name
+        entries.sort_by_key(|(is_file, name, _)| (*is_file, name.to_lowercase()));
+
+        for (is_file, name, path) in entries {
+            let icon: gio::Icon = if is_file {
+                let (content_type, _) = gio::content_type_guess(Some(&path), &[]);
+                gio::content_type_get_symbolic_icon(&content_type)
+            } else {
+                gio::ThemedIcon::new("folder-symbolic").upcast()
+            };
+            let iter = self.project_store.insert_with_values(
+                parent,
+                None,
+                &[(NAME, &name), (PATH, &path.display().to_string()), (IS_DIR, &!is_file), (ICON, &icon)],
+            );
+            if !is_file {
+                // no path, see `connect_test_expand_row`
+                self.project_store.insert_with_values(Some(&iter), None, &[(NAME, &"…"), (PATH, &"")]);
+            }
+        }
+    }
+
+    fn schedule_project_refresh(self: &Rc<Self>) {
+        if self.project_refresh_pending.replace(true) {
+            return;
+        }
+        let editor = self.clone();
+        glib::timeout_add_local_once(REFRESH_DELAY, move || {
+            editor.project_refresh_pending.set(false);
+            editor.refresh_project();
+        });
+    }
+
+    /// Mark files (and the folders they're in) with unsaved changes or a problem in their markdown.
+    pub(crate) fn update_file_status(&self) {
+        let documents = self.documents();
+        let store = self.project_store.clone();
+        self.project_store.foreach(|_, _, iter| {
+            let path = store.get::<String>(iter, PATH as i32);
+            if path.is_empty() {
+                return false;
+            }
+            let path = PathBuf::from(path);
+            let mut unsaved = false;
+            let mut problems = Vec::new();
+            for doc in &documents {
+                let Some(file) = doc.current_file.borrow().clone() else {
+                    continue;
+                };
+                if !file.starts_with(&path) {
+                    continue;
+                }
+                unsaved |= doc.md_buffer.is_modified();
+                if let Some(problem) = doc.problem.borrow().as_ref() {
+                    problems.push(format!("{}: {}", doc.name(), problem));
+                }
+            }
+
+            let status = match (problems.is_empty(), unsaved) {
+                (false, _) => "⚠",
+                (true, true) => "●",
+                (true, false) => "",
+            };
+            let mut tooltip = problems;
+            if unsaved {
+                tooltip.push("unsaved changes".to_string());
+            }
+            let tooltip = (!tooltip.is_empty()).then(|| glib::markup_escape_text(&tooltip.join("\n")).to_string());
+            store.set(iter, &[(STATUS, &status), (TOOLTIP, &tooltip)]);
+            false
+        });
+    }
+
+    // the selected row's path and whether it's a folder
+    fn selected_entry(&self) -> Option<(PathBuf, bool)> {
+        let (model, iter) = self.project_view.selection().selected()?;
+        let path = model.get::<String>(&iter, PATH as i32);
+        // not the placeholder in a folder that's still being opened
+        (!path.is_empty()).then(|| (PathBuf::from(path), model.get::<bool>(&iter, IS_DIR as i32)))
+    }
+
+    fn create_in_project(self: &Rc<Self>, folder: bool) {
+        // in the selected folder, next to the selected file, or at the top
+        let dir = match self.selected_entry() {
+            Some((path, true)) => Some(path),
+            Some((path, false)) => path.parent().map(Path::to_path_buf),
+            None => self.project_dir.borrow().clone(),
+        };
+        let Some(dir) = dir else {
+            return;
+        };
+        let title = if folder { "New folder name" } else { "New file name" };
+        let initial = if folder { "" } else { "untitled.md" };
+        let editor = self.clone();
+        self.ask_name(title, initial, move |name| {
+            let mut path = dir.join(name);
+            // a bare name is a page
+            if !folder && path.extension().is_none() {
+                path.set_extension("md");
+            }
+            if path.exists() {
+                editor.show_error(&format!("Couldn't create {}", path.display()), "There's already something with that name.");
+                return;
+            }
+            let result = if folder { fs::create_dir(&path) } else { fs::write(&path, "") };
+            if let Err(err) = result {
+                editor.show_error(&format!("Couldn't create {}", path.display()), &err.to_string());
+                return;
+            }
+            editor.refresh_project();
+            if is_markdown(&path) {
+                editor.open_path(&path);
+            }
+        });
+    }
+
+    fn rename_selected(self: &Rc<Self>) {
+        let Some((old, _)) = self.selected_entry() else {
+            return;
+        };
+        let (Some(dir), Some(name)) = (old.parent(), old.file_name()) else {
+            return;
+        };
+        let (dir, name) = (dir.to_path_buf(), name.to_string_lossy().into_owned());
+        let editor = self.clone();
+        self.ask_name("Rename to", &name, move |name| {
+            let new = dir.join(name);
+            if new == old {
+                return;
+            }
+            if new.exists() {
+                editor.show_error(&format!("Couldn't rename {}", old.display()), "There's already something with that name.");
+                return;
+            }
+            if let Err(err) = fs::rename(&old, &new) {
+                editor.show_error(&format!("Couldn't rename {}", old.display()), &err.to_string());
+                return;
+            }
+            // open documents follow their files, renamed folders included
+            for doc in editor.documents() {
+                let moved = doc.current_file.borrow().as_ref()
+                    .and_then(|file| file.strip_prefix(&old).ok())
+                    .map(|rest| new.join(rest));
+                if let Some(moved) = moved {
+                    *doc.current_file.borrow_mut() = Some(moved);
+                    doc.update_label();
+                }
+            }
+            editor.update_title();
+            editor.refresh_project();
+        });
+    }
+
+    fn delete_selected(self: &Rc<Self>) {
+        let Some((path, is_dir)) = self.selected_entry() else {
+            return;
+        };
+        let name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+        let dialog = MessageDialog::builder()
+            .transient_for(&self.window)
+            .modal(true)
+            .message_type(MessageType::Question)
+            .buttons(ButtonsType::None)
+            .text(format!("Move {} to the trash?", name))
+            .build();
+        if is_dir {
+            dialog.set_secondary_text(Some("Everything in the folder goes with it."));
+        }
+        dialog.add_buttons(&[("_Cancel", ResponseType::Cancel), ("_Move to Trash", ResponseType::Accept)]);
+        dialog.set_default_response(ResponseType::Cancel);
+
+        let editor = self.clone();
+        dialog.connect_response(move |dialog, response| {
+            dialog.close();
+            if response != ResponseType::Accept {
+                return;
+            }
+            if let Err(err) = gio::File::for_path(&path).trash(None::<&gio::Cancellable>) {
+                editor.show_error(&format!("Couldn't move {} to the trash", path.display()), &err.to_string());
+                return;
+            }
+            // what's still open is all that's left of it, so it counts as unsaved
+            for doc in editor.documents() {
+                let gone = doc.current_file.borrow().as_ref().is_some_and(|file| file.starts_with(&path));
+                if gone {
+                    *doc.current_file.borrow_mut() = None;
+                    doc.md_buffer.set_modified(true);
+                    doc.update_label();
+                }
+            }
+            editor.update_title();
+            editor.refresh_project();
+        });
+        dialog.present();
+    }
+
+    // a name for something in the project, `then` runs with it once it's entered
+    fn ask_name(self: &Rc<Self>, title: &str, initial: &str, then: impl Fn(&str) + 'static) {
+        let dialog = MessageDialog::builder()
+            .transient_for(&self.window)
+            .modal(true)
+            .message_type(MessageType::Question)
+            .buttons(ButtonsType::None)
+            .text(title)
+            .build();
+        dialog.add_buttons(&[("_Cancel", ResponseType::Cancel), ("_OK", ResponseType::Accept)]);
+        dialog.set_default_response(ResponseType::Accept);
+
+        let entry = Entry::new();
+        entry.set_text(initial);
+        entry.set_activates_default(true);
+        // select the name, not the extension
+        let stem = initial.rfind('.').filter(|&dot| dot > 0).unwrap_or(initial.len());
+        entry.select_region(0, initial[..stem].chars().count() as i32);
+        if let Ok(area) = dialog.message_area().downcast::<GtkBox>() {
+            area.append(&entry);
+        }
+
+        let editor = self.clone();
+        dialog.connect_response(move |dialog, response| {
+            let name = entry.text().trim().to_string();
+            dialog.close();
+            if response != ResponseType::Accept || name.is_empty() {
+                return;
+            }
+            // just a name, not somewhere else in the tree
+            if name.contains(['/', '\\']) || name == "." || name == ".." {
+                editor.show_error(&format!("Couldn't use '{}'", name), "Names can't contain slashes.");
+                return;
+            }
+            then(&name);
+        });
+        dialog.present();
+    }
+}
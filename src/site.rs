@@ -0,0 +1,581 @@
+use regex::{Captures, Regex};
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::markdown::{markdown_to_html, Options};
+use crate::template::render_page;
+
+// a directory can override the page template for everything below it with this file
+const TEMPLATE_FILE: &str = "_template.html";
+// `{{> name}}` in a template pulls in `_partials/name.html`
+const PARTIALS_DIR: &str = "_partials";
+// what was built from what, kept in the output directory between builds
+const STATE_FILE: &str = ".entermd-build";
+
+#[derive(Default)]
+pub struct BuildReport {
+    /// pages converted this time round
+    pub rendered: Vec<PathBuf>,
+    /// pages whose inputs haven't changed since the last build
+    pub unchanged: usize,
+    pub copied: Vec<PathBuf>,
+    /// outputs whose source has gone away
+    pub removed: Vec<PathBuf>,
+    /// files that failed, these don't stop the rest of the build
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+// every input a page was built from, with a hash of what it contained at the time
+type Deps = Vec<(PathBuf, u64)>;
+
+#[derive(Clone)]
+struct PageState {
+    hash: u64,
+    deps: Vec<PathBuf>,
+}
+
+#[derive(Default)]
+struct BuildState {
+    pages: HashMap<PathBuf, PageState>,
+    assets: HashSet<PathBuf>,
+}
+
+/// Convert every `.md` under `src` into `out`, copying everything else as-is.
+///
+/// Only pages whose markdown, template, partials or includes changed since the
+/// last build are converted again. Files and directories starting with `_` or
+/// `.` are not published. Pages with no `_template.html` above them use `template`.
+pub fn build_site(src: &Path, out: &Path, options: &Options, template: &str) -> io::Result<BuildReport> {
+    // building into a directory inside the content tree must not pick up old output
+    let skip = fs::canonicalize(out).ok();
+    let mut files = Vec::new();
+    collect_files(src, Path::new(""), skip.as_deref(), &mut files)?;
+    let (pages, assets): (Vec<PathBuf>, Vec<PathBuf>) = files.into_iter().partition(|rel| is_markdown(rel));
+
+    let old_state = BuildState::load(out);
+    let mut state = BuildState::default();
+    let mut report = BuildReport::default();
+
+    // work out which pages are stale by re-hashing the inputs they were built from last time
+    let mut file_hashes = HashMap::new();
+    let mut stale = Vec::new();
+    for rel in pages {
+        let output = out.join(rel.with_extension("html"));
+        match old_state.pages.get(&rel) {
+            Some(page) if output.exists() => {
+                let deps: Deps = page.deps.iter()
+                    .map(|dep| {
+                        let hash = *file_hashes.entry(dep.clone()).or_insert_with(|| hash_file(dep));
+                        (dep.clone(), hash)
+                    })
+                    .collect();
+                if inputs_hash(&deps, options, template) == page.hash {
+                    state.pages.insert(rel, PageState { hash: page.hash, deps: page.deps.clone() });
+                    report.unchanged += 1;
+                } else {
+                    stale.push(rel);
+                }
+            }
+            _ => stale.push(rel),
+        }
+    }
+
+    for (rel, result) in render_all(src, out, &stale, options, template) {
+        match result {
+            Ok(deps) => {
+                let hash = inputs_hash(&deps, options, template);
+                let deps = deps.into_iter().map(|(path, _)| path).collect();
+                state.pages.insert(rel.clone(), PageState { hash, deps });
+                report.rendered.push(rel);
+            }
+            Err(err) => {
+                // keep the old entry so the output is still cleaned up if the source goes away
+                if let Some(page) = old_state.pages.get(&rel) {
+                    state.pages.insert(rel.clone(), page.clone());
+                }
+                report.errors.push((rel, err));
+            }
+        }
+    }
+
+    for rel in assets {
+        match copy_if_changed(&src.join(&rel), &out.join(&rel)) {
+            Ok(true) => report.copied.push(rel.clone()),
+            Ok(false) => {}
+            Err(err) => report.errors.push((rel.clone(), err.to_string())),
+        }
+        state.assets.insert(rel);
+    }
+
+    // mirror deletions from the content tree
+    let gone_pages = old_state.pages.keys()
+        .filter(|rel| !state.pages.contains_key(*rel))
+        .map(|rel| rel.with_extension("html"));
+    let gone_assets = old_state.assets.iter()
+        .filter(|rel| !state.assets.contains(*rel))
+        .cloned();
+    for rel in gone_pages.chain(gone_assets) {
+        match fs::remove_file(out.join(&rel)) {
+            Ok(()) => report.removed.push(rel),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => report.errors.push((rel, err.to_string())),
+        }
+    }
+
+    state.save(out)?;
+    Ok(report)
+}
+
+/// Convert `pages` spread across all cores, returning each page's dependencies.
+fn render_all(src: &Path, out: &Path, pages: &[PathBuf], options: &Options, template: &str) -> Vec<(PathBuf, Result<Deps, String>)> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(pages.len());
+    let next = AtomicUsize::new(0);
+
+    let mut results: Vec<(usize, Result<Deps, String>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| scope.spawn(|| {
+                let mut done = Vec::new();
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(rel) = pages.get(i) else { break };
+                    // a converter bug costs this page, not the build; the panic message says what went wrong
+                    let rendered = panic::catch_unwind(AssertUnwindSafe(|| render_file(src, rel, options, template)))
+                        .unwrap_or_else(|_| Err("the converter crashed on this page".to_string()));
+                    let result = rendered.and_then(|(html, deps)| {
+                        write_file(&out.join(rel.with_extension("html")), html.as_bytes())
+                            .map_err(|err| err.to_string())?;
+                        Ok(deps)
+                    });
+                    done.push((i, result));
+                }
+                done
+            }))
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+    });
+
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(i, result)| (pages[i].clone(), result)).collect()
+}
+
+/// Convert the page at `rel` through its template, noting every file it was built from.
+fn render_file(src: &Path, rel: &Path, options: &Options, fallback: &str) -> Result<(String, Deps), String> {
+    let mut deps = Deps::new();
+    let markdown = read_with_includes(&src.join(rel), &mut deps, &mut Vec::new())?;
+    let template = load_template(src, rel, fallback, &mut deps)?;
+
+    let content = rewrite_md_links(&markdown_to_html(&markdown, options));
+    Ok((render_page(&template, &content, &root_prefix(rel)), deps))
+}
+
+/// Read a markdown file, replacing `{{include path}}` lines with the file they name.
+///
+/// Lines inside ``` fences are left alone, so pages can show the syntax.
+fn read_with_includes(path: &Path, deps: &mut Deps, stack: &mut Vec<PathBuf>) -> Result<String, String> {
+    lazy_static! {
+        static ref INCLUDE_REGEX: Regex = Regex::new(r"^\s*\{\{\s*include\s+(.+?)\s*\}\}\s*$").unwrap();
+    }
+    if stack.contains(&path.to_path_buf()) {
+        return Err(format!("{} includes itself", path.display()));
+    }
+    let markdown = read_dep(path, deps)?;
+
+    stack.push(path.to_path_buf());
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut lines = Vec::new();
+    let mut in_code = false;
+    for line in markdown.lines() {
+        // fences the way the converter sees them
+        if line.trim_end().starts_with("```") {
+            in_code = !in_code;
+        }
+        match INCLUDE_REGEX.captures(line).filter(|_| !in_code) {
+            Some(caps) => lines.push(read_with_includes(&dir.join(&caps[1]), deps, stack)?),
+            None => lines.push(line.to_string()),
+        }
+    }
+    stack.pop();
+
+    Ok(lines.join("\n"))
+}
+
+/// The nearest `_template.html` from the page's directory up, with its partials filled in,
+/// or `fallback` without one.
+fn load_template(src: &Path, rel: &Path, fallback: &str, deps: &mut Deps) -> Result<String, String> {
+    let mut dir = rel.parent();
+    while let Some(current) = dir {
+        let path = src.join(current).join(TEMPLATE_FILE);
+        if path.exists() {
+            let template = read_dep(&path, deps)?;
+            return expand_partials(src, &template, deps, &mut Vec::new());
+        }
+        // a template showing up here later changes this page too
+        deps.push((path.clone(), hash_file(&path)));
+        dir = current.parent();
+    }
+    Ok(fallback.to_string())
+}
+
+fn expand_partials(src: &Path, template: &str, deps: &mut Deps, stack: &mut Vec<String>) -> Result<String, String> {
+    lazy_static! {
+        static ref PARTIAL_REGEX: Regex = Regex::new(r"\{\{>\s*([\w./-]+)\s*\}\}").unwrap();
+    }
+    let mut error = None;
+    let expanded = PARTIAL_REGEX.replace_all(template, |caps: &Captures| {
+        let name = caps[1].to_string();
+        if stack.contains(&name) {
+            error.get_or_insert(format!("partial '{}' includes itself", name));
+            return String::new();
+        }
+        let path = src.join(PARTIALS_DIR).join(format!("{}.html", name));
+        stack.push(name);
+        let partial = read_dep(&path, deps).and_then(|partial| expand_partials(src, &partial, deps, stack));
+        stack.pop();
+        partial.unwrap_or_else(|err| {
+            error.get_or_insert(err);
+            String::new()
+        })
+    }).into_owned();
+
+    match error {
+        Some(err) => Err(err),
+        None => Ok(expanded),
+    }
+}
+
+// read a file the page depends on, remembering what it contained
+fn read_dep(path: &Path, deps: &mut Deps) -> Result<String, String> {
+    let contents = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    deps.push((path.to_path_buf(), hash_bytes(Some(contents.as_bytes()))));
+    Ok(contents)
+}
+
+fn hash_file(path: &Path) -> u64 {
+    hash_bytes(fs::read(path).ok().as_deref())
+}
+
+// `None` for files that don't exist
+fn hash_bytes(contents: Option<&[u8]>) -> u64 {
+    let mut hasher = StableHasher::new();
+    match contents {
+        Some(contents) => hasher.write_field(contents),
+        None => hasher.write(&[0xff]),
+    }
+    hasher.0
+}
+
+fn inputs_hash(deps: &Deps, options: &Options, template: &str) -> u64 {
+    let mut hasher = StableHasher::new();
+    // a new entermd may convert things differently
+    hasher.write_field(env!("CARGO_PKG_VERSION").as_bytes());
+    // the built-in or configured template, for pages without their own
+    hasher.write_field(template.as_bytes());
+    let Options { tables, strikethrough, image_size, dash_replacement } = *options;
+    hasher.write(&[tables, strikethrough, image_size, dash_replacement].map(u8::from));
+    for (path, hash) in deps {
+        hasher.write_field(path.as_os_str().as_encoded_bytes());
+        hasher.write(&hash.to_le_bytes());
+    }
+    hasher.0
+}
+
+// 64 bit FNV-1a. The hashes are saved between builds, so unlike std's hasher they
+// must come out the same from every build of entermd.
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> Self {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    // length first, so "ab" then "c" doesn't hash like "a" then "bc"
+    fn write_field(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+}
+
+impl BuildState {
+    // one line per output: `page <hash> <source> <deps>...` or `asset <source>`, tab
+    // separated, with tabs, newlines and backslashes in paths escaped
+    fn load(out: &Path) -> Self {
+        let mut state = BuildState::default();
+        let Ok(contents) = fs::read_to_string(out.join(STATE_FILE)) else {
+            return state;
+        };
+        for line in contents.lines() {
+            let mut fields = line.split('\t');
+            match (fields.next(), fields.next()) {
+                (Some("page"), Some(hash)) => {
+                    let (Ok(hash), Some(rel)) = (u64::from_str_radix(hash, 16), fields.next()) else {
+                        continue;
+                    };
+                    let deps = fields.map(unescape_path).collect();
+                    state.pages.insert(unescape_path(rel), PageState { hash, deps });
+                }
+                (Some("asset"), Some(rel)) => {
+                    state.assets.insert(unescape_path(rel));
+                }
+                _ => {}
+            }
+        }
+        state
+    }
+
+    fn save(&self, out: &Path) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for (rel, page) in &self.pages {
+            let mut fields = vec!["page".to_string(), format!("{:x}", page.hash), escape_path(rel)];
+            fields.extend(page.deps.iter().map(|dep| escape_path(dep)));
+            lines.push(fields.join("\t"));
+        }
+        for rel in &self.assets {
+            lines.push(format!("asset\t{}", escape_path(rel)));
+        }
+        lines.sort();
+        write_file(&out.join(STATE_FILE), (lines.join("\n") + "\n").as_bytes())
+    }
+}
+
+fn escape_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape_path(field: &str) -> PathBuf {
+    let mut path = String::new();
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            path.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => path.push('\t'),
+            Some('n') => path.push('\n'),
+            Some(c) => path.push(c),
+            None => {}
+        }
+    }
+    PathBuf::from(path)
+}
+
+fn collect_files(root: &Path, rel: &Path, skip: Option<&Path>, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let dir = root.join(rel);
+    let mut entries = fs::read_dir(&dir)
+        .map_err(|err| with_path(err, &dir))?
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(|err| with_path(err, &dir))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with(['_', '.']) {
+            continue;
+        }
+        let path = entry.path();
+        if skip.is_some() && fs::canonicalize(&path).ok().as_deref() == skip {
+            continue;
+        }
+
+        let rel = rel.join(&name);
+        if path.is_dir() {
+            collect_files(root, &rel, skip, files)?;
+        } else {
+            files.push(rel);
+        }
+    }
+    Ok(())
+}
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "md")
+}
+
+/// `../` once per directory between the site root and the page at `rel`.
+fn root_prefix(rel: &Path) -> String {
+    let depth = rel.components().count().saturating_sub(1);
+    "../".repeat(depth)
+}
+
+/// Point relative links at `.md` files to the `.html` they are built into.
+fn rewrite_md_links(html: &str) -> String {
+    lazy_static! {
+        static ref MD_LINK_REGEX: Regex = Regex::new(r#"href="([^"]*?)\.md(#[^"]*)?""#).unwrap();
+    }
+    MD_LINK_REGEX.replace_all(html, |caps: &Captures| {
+        let target = &caps[1];
+        // leave links to other sites alone
+        if target.contains("://") || target.starts_with("//") {
+            return caps[0].to_string();
+        }
+        let fragment = caps.get(2).map_or("", |m| m.as_str());
+        format!("href=\"{}.html{}\"", target, fragment)
+    }).into_owned()
+}
+
+// copies `source` unless `target` already holds the same bytes, returns whether it did
+fn copy_if_changed(source: &Path, target: &Path) -> io::Result<bool> {
+    let source_meta = fs::metadata(source).map_err(|err| with_path(err, source))?;
+    // only files of the same size need reading to compare
+    if fs::metadata(target).is_ok_and(|target_meta| target_meta.len() == source_meta.len())
+        && fs::read(source).map_err(|err| with_path(err, source))? == fs::read(target).unwrap_or_default()
+    {
+        return Ok(false);
+    }
+    create_parent(target)?;
+    fs::copy(source, target).map_err(|err| with_path(err, source))?;
+    Ok(true)
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    create_parent(path)?;
+    fs::write(path, contents).map_err(|err| with_path(err, path))
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).map_err(|err| with_path(err, parent)),
+        None => Ok(()),
+    }
+}
+
+// io errors don't say which file they came from
+fn with_path(err: io::Error, path: &Path) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::HTML_TEMPLATE;
+    use crate::testing::TempDir;
+
+    fn paths(paths: &[PathBuf]) -> Vec<&str> {
+        let mut paths: Vec<&str> = paths.iter().map(|path| path.to_str().unwrap()).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn rebuilds_only_pages_whose_inputs_changed() {
+        let dir = TempDir::new("incremental");
+        let (src, out) = (dir.join("src"), dir.join("out"));
+        dir.write("src/index.md", "# Home\n{{include parts/intro.md}}");
+        dir.write("src/parts/intro.md", "hello");
+        dir.write("src/blog/_template.html", "<nav>{{> nav}}</nav>{}");
+        dir.write("src/_partials/nav.html", "home");
+        dir.write("src/blog/post.md", "a post");
+        dir.write("src/style.css", "body {}");
+        let options = Options::default();
+
+        let report = build_site(&src, &out, &options, HTML_TEMPLATE).unwrap();
+        assert_eq!(paths(&report.rendered), vec!["blog/post.md", "index.md", "parts/intro.md"]);
+        assert_eq!(paths(&report.copied), vec!["style.css"]);
+
+        let report = build_site(&src, &out, &options, HTML_TEMPLATE).unwrap();
+        assert!(report.rendered.is_empty() && report.copied.is_empty());
+        assert_eq!(report.unchanged, 3);
+
+        dir.write("src/_partials/nav.html", "home | blog");
+        assert_eq!(paths(&build_site(&src, &out, &options, HTML_TEMPLATE).unwrap().rendered), vec!["blog/post.md"]);
+        dir.write("src/parts/intro.md", "hi");
+        assert_eq!(paths(&build_site(&src, &out, &options, HTML_TEMPLATE).unwrap().rendered), vec!["index.md", "parts/intro.md"]);
+        // a template appearing nearer the page takes over
+        dir.write("src/parts/_template.html", "{}");
+        assert_eq!(paths(&build_site(&src, &out, &options, HTML_TEMPLATE).unwrap().rendered), vec!["parts/intro.md"]);
+        // so does converting with other options
+        assert_eq!(build_site(&src, &out, &Options::gfm(), HTML_TEMPLATE).unwrap().unchanged, 0);
+
+        fs::remove_file(src.join("blog/post.md")).unwrap();
+        assert_eq!(paths(&build_site(&src, &out, &Options::gfm(), HTML_TEMPLATE).unwrap().removed), vec!["blog/post.html"]);
+        assert!(!out.join("blog/post.html").exists());
+    }
+
+    #[test]
+    fn includes_in_code_blocks_are_left_as_written() {
+        let dir = TempDir::new("fenced-include");
+        dir.write("page.md", "{{include part.md}}\n```\n{{include missing.md}}\n{{> nav}}\n```\n{{include part.md}}");
+        dir.write("part.md", "included");
+        let mut deps = Deps::new();
+        let markdown = read_with_includes(&dir.join("page.md"), &mut deps, &mut Vec::new()).unwrap();
+        assert_eq!(markdown, "included\n```\n{{include missing.md}}\n{{> nav}}\n```\nincluded");
+    }
+
+    #[test]
+    fn the_configured_template_is_below_the_site_templates() {
+        let dir = TempDir::new("fallback");
+        let (src, out) = (dir.join("src"), dir.join("out"));
+        dir.write("src/index.md", "home");
+        dir.write("src/blog/_template.html", "<main>{}</main>");
+        dir.write("src/blog/post.md", "a post");
+
+        let report = build_site(&src, &out, &Options::default(), "<body>{}</body>").unwrap();
+        assert_eq!(report.rendered.len(), 2);
+        assert!(fs::read_to_string(out.join("index.html")).unwrap().starts_with("<body>"));
+        assert!(fs::read_to_string(out.join("blog/post.html")).unwrap().starts_with("<main>"));
+        // changing the configured template builds the site again, like a new entermd would
+        let report = build_site(&src, &out, &Options::default(), "<article>{}</article>").unwrap();
+        assert_eq!(report.unchanged, 0);
+        assert!(fs::read_to_string(out.join("index.html")).unwrap().starts_with("<article>"));
+    }
+
+    #[test]
+    fn a_broken_page_does_not_stop_the_others() {
+        let dir = TempDir::new("errors");
+        let (src, out) = (dir.join("src"), dir.join("out"));
+        dir.write("src/a.md", "{{include missing.md}}");
+        dir.write("src/b.md", "```\nnever closed");
+        let report = build_site(&src, &out, &Options::default(), HTML_TEMPLATE).unwrap();
+        assert_eq!(paths(&report.rendered), vec!["b.md"]);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, PathBuf::from("a.md"));
+        assert!(report.errors[0].1.contains("missing.md"));
+    }
+
+    #[test]
+    fn assets_are_copied_when_their_contents_change() {
+        let dir = TempDir::new("assets");
+        let (source, target) = (dir.join("a.css"), dir.join("out/a.css"));
+        dir.write("a.css", "one");
+        assert!(copy_if_changed(&source, &target).unwrap());
+        assert!(!copy_if_changed(&source, &target).unwrap());
+        // same size, and however old the source's mtime is
+        dir.write("a.css", "two");
+        assert!(copy_if_changed(&source, &target).unwrap());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "two");
+    }
+
+    #[test]
+    fn state_survives_awkward_paths() {
+        let dir = TempDir::new("state");
+        let mut state = BuildState::default();
+        let deps = vec![PathBuf::from("src/tab\there.md"), PathBuf::from("src/back\\slash.md")];
+        state.pages.insert(PathBuf::from("new\nline.md"), PageState { hash: 0xdead_beef, deps: deps.clone() });
+        state.assets.insert(PathBuf::from("a\tb.css"));
+        state.save(dir.path()).unwrap();
+
+        let loaded = BuildState::load(dir.path());
+        let page = &loaded.pages[&PathBuf::from("new\nline.md")];
+        assert_eq!((page.hash, &page.deps), (0xdead_beef, &deps));
+        assert!(loaded.assets.contains(&PathBuf::from("a\tb.css")));
+    }
+
+    #[test]
+    fn hashes_are_fixed() {
+        // these are saved between builds, a change here rebuilds everyone's sites
+        assert_eq!(hash_bytes(None), 0xaf64_724c_8602_eb6e);
+        assert_ne!(hash_bytes(Some(b"")), hash_bytes(None));
+    }
+}
@@ -0,0 +1,184 @@
+use gtk4::prelude::*;
+use gtk4::gio::SimpleAction;
+use gtk4::{EventControllerMotion, GestureClick, TextBuffer, TextTag, TextView, TextWindowType};
+use std::rc::Rc;
+
+use crate::document::Document;
+use crate::markdown::SourceBlock;
+use crate::MarkdownEditor;
+
+// marks the html made from the block under the markdown cursor
+const CURRENT_BLOCK_TAG: &str = "current-block";
+
+impl MarkdownEditor {
+    /// Clicking a line of the HTML pane moves the markdown cursor to where it came from,
+    /// and the block under the markdown cursor is highlighted in the HTML.
+    pub(crate) fn connect_document_source_map(self: &Rc<Self>, doc: &Rc<Document>) {
+        doc.html_buffer.tag_table().add(&TextTag::builder()
+            .name(CURRENT_BLOCK_TAG)
+            .paragraph_background("rgba(255, 200, 0, 0.15)")
+            .build());
+
+        let click_controller = GestureClick::new();
+        let weak = Rc::downgrade(doc);
+        click_controller.connect_pressed(move |_, _, x, y| {
+            let Some(doc) = weak.upgrade() else {
+                return;
+            };
+            let (x, y) = doc.html_view.window_to_buffer_coords(TextWindowType::Widget, x as i32, y as i32);
+            if let Some(iter) = doc.html_view.iter_at_location(x, y) {
+                jump_to_source(&doc, iter.line() as usize);
+            }
+            doc.text_view.grab_focus();
+        });
+        doc.html_view.add_controller(click_controller);
+
+        let html_buffer = doc.html_buffer.clone();
+        let source_map = doc.source_map.clone();
+        doc.md_buffer.connect_cursor_position_notify(move |md_buffer| {
+            highlight_current_block(md_buffer, &html_buffer, &source_map.borrow());
+        });
+    }
+
+    /// Keep the two panes scrolled to the same blocks, toggled by the `win.sync-scroll` action.
+    pub(crate) fn connect_scroll_sync(self: &Rc<Self>) {
+        let sync_action = SimpleAction::new_stateful("sync-scroll", None, &true.to_variant());
+        let editor = self.clone();
+        sync_action.connect_activate(move |action, _| {
+            let enabled = !editor.scroll_sync.get();
+            editor.scroll_sync.set(enabled);
+            action.set_state(&enabled.to_variant());
+            let doc = editor.doc();
+            if enabled {
+                sync_scroll(&doc, false);
+            }
+            doc.text_view.grab_focus();
+        });
+        self.window.add_action(&sync_action);
+    }
+
+    /// Panes are lined up block by block through the source map rather than by
+    /// scroll percentage, so a long table or code block doesn't throw them out.
+    pub(crate) fn connect_document_scroll_sync(self: &Rc<Self>, doc: &Rc<Document>) {
+        let (Some(md_adjustment), Some(html_adjustment)) = (doc.text_view.vadjustment(), doc.html_view.vadjustment()) else {
+            return;
+        };
+
+        let editor = self.clone();
+        let weak = Rc::downgrade(doc);
+        md_adjustment.connect_value_changed(move |_| {
+            if let Some(doc) = weak.upgrade() {
+                if editor.scroll_sync.get() && !doc.syncing.get() {
+                    sync_scroll(&doc, false);
+                }
+            }
+        });
+
+        // the HTML pane can't take focus, so it's only ever scrolled with the pointer over it;
+        // anything else moving it (a re-render) mustn't drag the markdown along
+        let html_hover = EventControllerMotion::new();
+        match doc.html_view.parent() {
+            Some(scrolled) => scrolled.add_controller(html_hover.clone()),
+            None => doc.html_view.add_controller(html_hover.clone()),
+        }
+        let editor = self.clone();
+        let weak = Rc::downgrade(doc);
+        html_adjustment.connect_value_changed(move |_| {
+            if let Some(doc) = weak.upgrade() {
+                if editor.scroll_sync.get() && !doc.syncing.get() && html_hover.contains_pointer() {
+                    sync_scroll(&doc, true);
+                }
+            }
+        });
+
+        // the page was re-rendered, put it back in line with the markdown
+        let editor = self.clone();
+        let weak = Rc::downgrade(doc);
+        html_adjustment.connect_changed(move |_| {
+            if let Some(doc) = weak.upgrade() {
+                if editor.scroll_sync.get() && !doc.syncing.get() {
+                    sync_scroll(&doc, false);
+                }
+            }
+        });
+    }
+}
+
+// scroll one pane to the blocks showing at the top of the other
+fn sync_scroll(doc: &Document, from_html: bool) {
+    // (markdown line, html line) pairs that belong together, both in order
+    let mut anchors = Vec::new();
+    for block in doc.source_map.borrow().iter() {
+        anchors.push((block.source.start as f64, block.html.start as f64));
+        anchors.push((block.source.end as f64, block.html.end as f64));
+    }
+    anchors.push((doc.md_buffer.line_count() as f64, doc.html_buffer.line_count() as f64));
+
+    let (from, to) = if from_html {
+        for anchor in &mut anchors {
+            *anchor = (anchor.1, anchor.0);
+        }
+        (&doc.html_view, &doc.text_view)
+    } else {
+        (&doc.text_view, &doc.html_view)
+    };
+    let (Some(from_adjustment), Some(to_adjustment)) = (from.vadjustment(), to.vadjustment()) else {
+        return;
+    };
+
+    let line = interpolate(&anchors, top_line(from, from_adjustment.value()));
+    let buffer = to.buffer();
+    let iter = buffer.iter_at_line(line as i32).unwrap_or_else(|| buffer.end_iter());
+    let (y, height) = to.line_yrange(&iter);
+    doc.syncing.set(true);
+    to_adjustment.set_value(y as f64 + line.fract() * height as f64);
+    doc.syncing.set(false);
+}
+
+fn jump_to_source(doc: &Document, html_line: usize) {
+    let source_map = doc.source_map.borrow();
+    let Some(block) = source_map.iter().find(|block| block.html.contains(&html_line)) else {
+        return;
+    };
+    if let Some(iter) = doc.md_buffer.iter_at_line(block.source.start as i32) {
+        doc.md_buffer.place_cursor(&iter);
+        doc.text_view.scroll_to_mark(&doc.md_buffer.get_insert(), 0.1, false, 0.0, 0.0);
+    }
+}
+
+// the line at the top of `view` scrolled to `y`, with how much of it is scrolled past as the fraction
+fn top_line(view: &TextView, y: f64) -> f64 {
+    let (iter, top) = view.line_at_y(y as i32);
+    let (_, height) = view.line_yrange(&iter);
+    let scrolled_past = if height > 0 { (y - top as f64) / height as f64 } else { 0.0 };
+    iter.line() as f64 + scrolled_past.clamp(0.0, 1.0)
+}
+
+// piecewise linear through `points` (sorted by x), starting from (0, 0)
+fn interpolate(points: &[(f64, f64)], x: f64) -> f64 {
+    let mut previous = (0.0, 0.0);
+    for &point in points {
+        if x < point.0 {
+            return previous.1 + (x - previous.0) / (point.0 - previous.0) * (point.1 - previous.1);
+        }
+        previous = point;
+    }
+    previous.1
+}
+
+/// Highlight the html made from the markdown block the cursor is in, if it's in one.
+///
+/// `source_map` is in lines of the whole page shown in `html_buffer`.
+pub fn highlight_current_block(md_buffer: &TextBuffer, html_buffer: &TextBuffer, source_map: &[SourceBlock]) {
+    html_buffer.remove_tag_by_name(CURRENT_BLOCK_TAG, &html_buffer.start_iter(), &html_buffer.end_iter());
+
+    let cursor_line = md_buffer.iter_at_mark(&md_buffer.get_insert()).line() as usize;
+    let Some(block) = source_map.iter().find(|block| block.source.contains(&cursor_line)) else {
+        return;
+    };
+    let start = html_buffer.iter_at_line(block.html.start as i32);
+    let end = html_buffer.iter_at_line(block.html.end as i32).unwrap_or_else(|| html_buffer.end_iter());
+    if let Some(start) = start {
+        html_buffer.apply_tag_by_name(CURRENT_BLOCK_TAG, &start, &end);
+    }
+}
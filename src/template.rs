@@ -0,0 +1,36 @@
+// html template with proper indentation, `{}` marks where the converted markdown goes
+// and `{root}` is the relative path from the page back up to the site root
+pub const HTML_TEMPLATE: &str = "<!DOCTYPE html>\n\
+     <html lang=\"en\">\n\
+     <head>\n\
+     \t<meta charset=\"UTF-8\">\n\
+     \t<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">\n\
+     \t<meta name=\"description\" content=\"e-sayin website description\">\n\
+     \t<meta name=\"color-scheme\" content=\"light-only\">\n\
+     \t<link rel=\"stylesheet\" href=\"{root}style.css\">\n\
+     </head>\n\
+     <body>\n\
+     \n\
+     {}\n\
+     </body>\n\
+     </html>";
+
+// the editor and `convert` don't know where a page lives, so assume the usual one level deep
+pub const DEFAULT_ROOT: &str = "../";
+
+/// Wrap converted markdown in `template` for a page `root` (e.g. `"../"`) below the site root.
+pub fn render_page(template: &str, content: &str, root: &str) -> String {
+    // indent the content by 4 spaces to align with template
+    let indented_content: String = content
+        .lines()
+        .map(|line| if !line.trim().is_empty() { format!("    {}", line) } else { line.to_string() })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    template.replace("{root}", root).replace("{}", &indented_content)
+}
+
+/// The line of a page rendered from `template` where the converted markdown starts.
+pub fn content_line(template: &str) -> usize {
+    template.find("{}").map_or(0, |start| template[..start].matches('\n').count())
+}
@@ -0,0 +1,39 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A fresh directory under the system temp dir for a test, removed again when dropped,
+/// assertion failures included.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` keeps tests running at the same time out of each other's way.
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("entermd-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, rel: &str) -> PathBuf {
+        self.0.join(rel)
+    }
+
+    /// Write `contents` to `rel`, making the directories on the way.
+    pub fn write(&self, rel: &str, contents: &str) {
+        let path = self.join(rel);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, contents).unwrap();
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,294 @@
+use gtk4::prelude::*;
+use gtk4::gio::{self, DBusCallFlags, DBusProxy, DBusProxyFlags, FileMonitorEvent, FileMonitorFlags, Menu, SimpleAction};
+use gtk4::GestureClick;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::config::Config;
+use crate::settings::config_dir;
+use crate::{highlight, MarkdownEditor, DARK_MODE_CSS, LIGHT_MODE_CSS};
+
+// picker label and settings name of the themes that come with the editor
+pub const BUILTIN_THEMES: [(&str, &str); 3] = [("Light", "light"), ("Dark", "dark"), ("Follow the desktop", "system")];
+
+/// Where user themes live, one `<name>.css` per theme.
+pub fn themes_dir() -> PathBuf {
+    config_dir().join("themes")
+}
+
+/// Names of the user themes, sorted. Files named after a built-in theme are left out.
+pub fn user_themes() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(themes_dir()) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "css"))
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .filter(|name| !BUILTIN_THEMES.iter().any(|(_, builtin)| builtin == name))
+        .collect();
+    names.sort();
+    names
+}
+
+// where the settings portal keeps the desktop's light or dark preference
+const APPEARANCE: &str = "org.freedesktop.appearance";
+const COLOR_SCHEME: &str = "color-scheme";
+
+// the portal's color-scheme as whether the desktop wants dark apps, None if it has no preference
+fn color_scheme_dark(mut value: glib::Variant) -> Option<bool> {
+    // `Read` boxes the value in a variant or two
+    while let Some(inner) = value.as_variant() {
+        value = inner;
+    }
+    // 1 is dark, 2 light, 0 no preference
+    match value.get::<u32>()? {
+        1 => Some(true),
+        2 => Some(false),
+        _ => None,
+    }
+}
+
+// a user theme says which syntax colours suit it with a `/* entermd: dark */` (or light) comment
+fn declared_dark(css: &str) -> Option<bool> {
+    lazy_static! {
+        static ref SCHEME_REGEX: Regex = Regex::new(r"/\*\s*entermd:\s*(dark|light)\s*\*/").unwrap();
+    }
+    SCHEME_REGEX.captures(css).map(|caps| &caps[1] == "dark")
+}
+
+fn fill_user_section(section: &Menu) {
+    section.remove_all();
+    for name in user_themes() {
+        section.append(Some(&name), Some(&format!("win.theme::{}", name)));
+    }
+}
+
+impl MarkdownEditor {
+    /// Show the configured theme, pick another from the theme button's menu, follow the
+    /// desktop while set to system, and reload user themes when their files change.
+    pub(crate) fn connect_theme(self: &Rc<Self>, config: &Config) {
+        *self.theme.borrow_mut() = config.theme.clone().unwrap_or_else(|| "system".to_string());
+
+        // gtk reports problems while the css loads, so they land on the current theme
+        let editor = self.clone();
+        self.css_provider.connect_parsing_error(move |_, section, error| {
+            let location = section.start_location();
+            let theme = editor.theme.borrow().clone();
+            editor.show_theme_error(&format!(
+                "{}.css:{}:{}: {}",
+                theme,
+                location.lines() + 1,
+                location.line_chars() + 1,
+                error.message()
+            ));
+        });
+
+        let dismiss = GestureClick::new();
+        let label = self.theme_errors.clone();
+        dismiss.connect_pressed(move |_, _, _, _| label.set_visible(false));
+        self.theme_errors.add_controller(dismiss);
+
+        let initial = self.theme.borrow().clone();
+        let theme = SimpleAction::new_stateful("theme", Some(glib::VariantTy::STRING), &initial.to_variant());
+        let editor = self.clone();
+        theme.connect_activate(move |action, name| {
+            let Some(name) = name.and_then(|name| name.get::<String>()) else {
+                return;
+            };
+            action.set_state(&name.to_variant());
+            if let Err(err) = Config::save_user_theme(&name) {
+                eprintln!("entermd: couldn't save the theme: {}", err);
+            }
+            *editor.theme.borrow_mut() = name;
+            editor.apply_theme();
+
+            // Return focus to markdown input after theme change
+            editor.doc().text_view.grab_focus();
+        });
+        self.window.add_action(&theme);
+
+        let open_folder = SimpleAction::new("open-themes-folder", None);
+        open_folder.connect_activate(move |_, _| {
+            let dir = themes_dir();
+            if let Err(err) = fs::create_dir_all(&dir) {
+                eprintln!("entermd: couldn't create {}: {}", dir.display(), err);
+                return;
+            }
+            let uri = gio::File::for_path(&dir).uri();
+            if let Err(err) = gio::AppInfo::launch_default_for_uri(&uri, None::<&gio::AppLaunchContext>) {
+                eprintln!("entermd: couldn't open {}: {}", dir.display(), err);
+            }
+        });
+        self.window.add_action(&open_folder);
+
+        let menu = Menu::new();
+        let builtin = Menu::new();
+        for (label, name) in BUILTIN_THEMES {
+            builtin.append(Some(label), Some(&format!("win.theme::{}", name)));
+        }
+        menu.append_section(None, &builtin);
+        let user = Menu::new();
+        fill_user_section(&user);
+        menu.append_section(None, &user);
+        let folder = Menu::new();
+        folder.append(Some("Open Themes Folder"), Some("win.open-themes-folder"));
+        menu.append_section(None, &folder);
+        self.theme_button.set_menu_model(Some(&menu));
+
+        // new, edited and removed theme files show up without a restart
+        match gio::File::for_path(themes_dir()).monitor_directory(FileMonitorFlags::NONE, None::<&gio::Cancellable>) {
+            Ok(monitor) => {
+                let editor = self.clone();
+                monitor.connect_changed(move |_, file, _, event| {
+                    if !matches!(event, FileMonitorEvent::ChangesDoneHint | FileMonitorEvent::Created | FileMonitorEvent::Deleted) {
+                        return;
+                    }
+                    fill_user_section(&user);
+                    let current = format!("{}.css", editor.theme.borrow());
+                    if file.basename().is_some_and(|name| name.as_os_str() == current.as_str()) {
+                        editor.apply_theme();
+                    }
+                });
+                *self.themes_monitor.borrow_mut() = Some(monitor);
+            }
+            Err(err) => eprintln!("entermd: couldn't watch {}: {}", themes_dir().display(), err),
+        }
+
+        self.follow_settings_portal();
+        if let Some(gtk_settings) = gtk4::Settings::default() {
+            let editor = self.clone();
+            gtk_settings.connect_gtk_application_prefer_dark_theme_notify(move |_| editor.apply_theme());
+        }
+
+        self.apply_theme();
+    }
+
+    pub(crate) fn apply_theme(&self) {
+        self.theme_errors.set_text("");
+        self.theme_errors.set_visible(false);
+
+        let theme = self.theme.borrow().clone();
+        let dark = match theme.as_str() {
+            "light" => false,
+            "dark" => true,
+            "system" => self.desktop_prefers_dark(),
+            name => {
+                let path = themes_dir().join(format!("{}.css", name));
+                match fs::read_to_string(&path) {
+                    Ok(css) => {
+                        self.css_provider.load_from_data(&css);
+                        declared_dark(&css).unwrap_or_else(|| self.desktop_prefers_dark())
+                    }
+                    Err(err) => {
+                        self.css_provider.load_from_data(LIGHT_MODE_CSS);
+                        self.show_theme_error(&format!("{}: {}", path.display(), err));
+                        false
+                    }
+                }
+            }
+        };
+        if matches!(theme.as_str(), "light" | "dark" | "system") {
+            self.css_provider.load_from_data(if dark { DARK_MODE_CSS } else { LIGHT_MODE_CSS });
+        }
+        if dark {
+            self.theme_button.add_css_class("dark");
+        } else {
+            self.theme_button.remove_css_class("dark");
+        }
+        self.dark.set(dark);
+        for doc in self.documents() {
+            highlight::set_dark(&doc.md_buffer, dark);
+            highlight::set_dark(&doc.html_buffer, dark);
+        }
+
+        let (icon, tooltip) = match theme.as_str() {
+            "light" => ("weather-clear-symbolic", "Theme: light".to_string()),
+            "dark" => ("weather-clear-night-symbolic", "Theme: dark".to_string()),
+            "system" => ("emblem-system-symbolic", "Theme: follow the desktop".to_string()),
+            name => ("applications-graphics-symbolic", format!("Theme: {}", name)),
+        };
+        self.theme_button.set_icon_name(icon);
+        self.theme_button.set_tooltip_text(Some(&tooltip));
+    }
+
+    // the settings portal's color-scheme, or GTK's prefer-dark setting without one
+    fn desktop_prefers_dark(&self) -> bool {
+        self.portal_dark.get().unwrap_or_else(|| {
+            gtk4::Settings::default().is_some_and(|settings| settings.is_gtk_application_prefer_dark_theme())
+        })
+    }
+
+    // read the desktop's color-scheme from the settings portal, then keep up with it;
+    // all asynchronous, so a missing portal never holds up the window
+    fn follow_settings_portal(self: &Rc<Self>) {
+        let editor = self.clone();
+        glib::spawn_future_local(async move {
+            let portal = DBusProxy::for_bus_future(
+                gio::BusType::Session,
+                DBusProxyFlags::DO_NOT_LOAD_PROPERTIES,
+                None,
+                "org.freedesktop.portal.Desktop",
+                "/org/freedesktop/portal/desktop",
+                "org.freedesktop.portal.Settings",
+            )
+            .await;
+            let Ok(portal) = portal else {
+                return;
+            };
+            let weak = Rc::downgrade(&editor);
+            portal.connect_local("g-signal", false, move |values| {
+                let editor = weak.upgrade()?;
+                let signal = values[2].get::<String>().ok()?;
+                let parameters = values[3].get::<glib::Variant>().ok()?;
+                let (namespace, key, value) = parameters.get::<(String, String, glib::Variant)>()?;
+                if signal == "SettingChanged" && namespace == APPEARANCE && key == COLOR_SCHEME {
+                    editor.set_portal_dark(color_scheme_dark(value));
+                }
+                None
+            });
+            *editor.settings_portal.borrow_mut() = Some(portal.clone());
+
+            let reply = portal
+                .call_future("Read", Some(&(APPEARANCE, COLOR_SCHEME).to_variant()), DBusCallFlags::NONE, 500)
+                .await;
+            if let Ok(reply) = reply {
+                editor.set_portal_dark(color_scheme_dark(reply.child_value(0)));
+            }
+        });
+    }
+
+    fn set_portal_dark(&self, dark: Option<bool>) {
+        if self.portal_dark.replace(dark) != dark {
+            self.apply_theme();
+        }
+    }
+
+    // add a line to the banner over the HTML pane
+    fn show_theme_error(&self, message: &str) {
+        eprintln!("entermd: theme: {}", message);
+        let text = self.theme_errors.text();
+        if text.is_empty() {
+            self.theme_errors.set_text(message);
+        } else {
+            self.theme_errors.set_text(&format!("{}\n{}", text, message));
+        }
+        self.theme_errors.set_visible(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_themes_declare_their_syntax_colours() {
+        assert_eq!(declared_dark("/* entermd: dark */\ntextview { color: white; }"), Some(true));
+        assert_eq!(declared_dark("textview {}\n/*entermd:light*/"), Some(false));
+        assert_eq!(declared_dark("/* a dark theme */"), None);
+    }
+}
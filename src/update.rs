@@ -0,0 +1,188 @@
+use gtk4::prelude::*;
+use gtk4::gio;
+use gtk4::TextBuffer;
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::document::Document;
+use crate::markdown::{headings, IncrementalConverter, SourceBlock};
+use crate::sourcemap::highlight_current_block;
+use crate::template::{content_line, render_page, DEFAULT_ROOT};
+use crate::MarkdownEditor;
+
+/// How long typing has to pause before the preview catches up, unless entermd.toml says otherwise.
+pub const UPDATE_DELAY: Duration = Duration::from_millis(60);
+
+impl MarkdownEditor {
+    // typing, deleting, pasting and opening files all come through here
+    pub(crate) fn connect_document_updates(self: &Rc<Self>, doc: &Rc<Document>) {
+        let editor = self.clone();
+        let weak = Rc::downgrade(doc);
+        doc.md_buffer.connect_changed(move |_| {
+            if let Some(doc) = weak.upgrade() {
+                editor.schedule_update(&doc);
+            }
+        });
+    }
+
+    /// Bring every document's HTML pane up to date, after the template or converter changed.
+    pub(crate) fn update_all(self: &Rc<Self>) {
+        for doc in self.documents() {
+            self.schedule_update(&doc);
+        }
+    }
+
+    /// Bring the document's HTML pane up to date once edits settle.
+    ///
+    /// Every edit bumps the generation and only the newest one gets converted, on
+    /// a worker thread. A conversion overtaken by later edits is abandoned.
+    pub(crate) fn schedule_update(self: &Rc<Self>, doc: &Rc<Document>) {
+        let generation = doc.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let editor = self.clone();
+        let weak = Rc::downgrade(doc);
+        glib::timeout_add_local_once(self.update_delay.get(), move || {
+            // closed in the meantime
+            let Some(doc) = weak.upgrade() else {
+                return;
+            };
+            if doc.generation.load(Ordering::SeqCst) == generation {
+                editor.start_update(&doc, generation);
+            }
+        });
+    }
+
+    fn start_update(self: &Rc<Self>, doc: &Rc<Document>, generation: u64) {
+        let markdown = doc.md_buffer.text(&doc.md_buffer.start_iter(), &doc.md_buffer.end_iter(), false).to_string();
+        let html_template = self.html_template.borrow().clone();
+        let shared = doc.converter.clone();
+        let options = self.options.get();
+        let latest = doc.generation.clone();
+
+        let conversion = gio::spawn_blocking(move || {
+            let stale = || latest.load(Ordering::SeqCst) != generation;
+            let mut converter = shared.lock().unwrap_or_else(|poisoned| {
+                // the last conversion panicked halfway, its cache can't be trusted
+                let mut converter = poisoned.into_inner();
+                *converter = IncrementalConverter::new(options);
+                converter
+            });
+            shared.clear_poison();
+            let (content, mut map, warnings) = converter.convert(&markdown, stale)?;
+
+            // the map counts lines of the converted markdown, the pane shows the whole page
+            let offset = content_line(&html_template);
+            for block in &mut map {
+                block.html = block.html.start + offset..block.html.end + offset;
+            }
+            let problem = warnings.first().map(|warning| warning.to_string());
+            Some((render_page(&html_template, &content, DEFAULT_ROOT), map, headings(&markdown), problem))
+        });
+
+        let editor = self.clone();
+        let weak = Rc::downgrade(doc);
+        glib::spawn_future_local(async move {
+            let result = conversion.await;
+            let Some(doc) = weak.upgrade() else {
+                return;
+            };
+            // the converter panicked; the sidebar says so until an edit converts cleanly
+            let Ok(result) = result else {
+                *doc.problem.borrow_mut() = Some("the converter crashed on this document".to_string());
+                editor.update_file_status();
+                return;
+            };
+            // overtaken by a later edit
+            let Some((html, map, outline, problem)) = result else {
+                return;
+            };
+            if doc.generation.load(Ordering::SeqCst) == generation {
+                // the sidebar flags files with something off in them
+                if *doc.problem.borrow() != problem {
+                    *doc.problem.borrow_mut() = problem;
+                    editor.update_file_status();
+                }
+                *doc.outline.borrow_mut() = outline;
+                editor.show_update(&doc, &html, map);
+            }
+        });
+    }
+
+    fn show_update(&self, doc: &Rc<Document>, html: &str, map: Vec<SourceBlock>) {
+        // only touch what changed so scroll and selection stay put
+        replace_changed(&doc.html_buffer, html);
+        *doc.source_map.borrow_mut() = map;
+        highlight_current_block(&doc.md_buffer, &doc.html_buffer, &doc.source_map.borrow());
+
+        // and the outline, search matches and any browser looking at it, if it's the tab being looked at
+        if Rc::ptr_eq(doc, &self.doc()) {
+            self.show_outline(doc);
+            self.update_search(doc);
+            if let Some(server) = &self.preview_server {
+                server.set_live_page(html);
+            }
+        }
+    }
+}
+
+// the byte ranges of `old` and `new` that differ, None when they don't
+fn changed_span(old: &str, new: &str) -> Option<(Range<usize>, Range<usize>)> {
+    let mut prefix = old.bytes().zip(new.bytes()).take_while(|(a, b)| a == b).count();
+    while !old.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let mut suffix = old.bytes().rev().zip(new.bytes().rev()).take(max_suffix).take_while(|(a, b)| a == b).count();
+    while !old.is_char_boundary(old.len() - suffix) {
+        suffix -= 1;
+    }
+    if prefix == old.len() && prefix == new.len() {
+        return None;
+    }
+    Some((prefix..old.len() - suffix, prefix..new.len() - suffix))
+}
+
+// swap the part of `buffer` that differs from `text` for the new text
+pub(crate) fn replace_changed(buffer: &TextBuffer, text: &str) {
+    let old = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+    let old = old.as_str();
+    let Some((removed, inserted)) = changed_span(old, text) else {
+        return;
+    };
+
+    let start = old[..removed.start].chars().count() as i32;
+    let end = start + old[removed].chars().count() as i32;
+    let mut start_iter = buffer.iter_at_offset(start);
+    let mut end_iter = buffer.iter_at_offset(end);
+    buffer.delete(&mut start_iter, &mut end_iter);
+    let inserted = &text[inserted];
+    if !inserted.is_empty() {
+        buffer.insert(&mut start_iter, inserted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what gets taken out of `old` and put in its place
+    fn replaced(old: &str, new: &str) -> Option<(String, String)> {
+        changed_span(old, new).map(|(removed, inserted)| (old[removed].to_string(), new[inserted].to_string()))
+    }
+
+    #[test]
+    fn only_the_difference_is_replaced() {
+        assert_eq!(changed_span("same", "same"), None);
+        assert_eq!(replaced("<p>one</p>", "<p>two</p>"), Some(("one".to_string(), "two".to_string())));
+        assert_eq!(replaced("ab", "abc"), Some((String::new(), "c".to_string())));
+        assert_eq!(replaced("aXa", "aa"), Some(("X".to_string(), String::new())));
+    }
+
+    #[test]
+    fn spans_end_on_char_boundaries() {
+        // é and è share their first byte
+        assert_eq!(replaced("café", "cafè"), Some(("é".to_string(), "è".to_string())));
+        assert_eq!(replaced("ñx", "ñyx"), Some((String::new(), "y".to_string())));
+    }
+}
@@ -0,0 +1,105 @@
+use notify::{Config, Event, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{channel, Sender};
+use std::time::{Duration, Instant};
+
+use crate::markdown::Options;
+use crate::site::{build_site, BuildReport};
+
+// how long the tree has to be quiet before a rebuild, so a save touching several files builds once
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Build `src` into `out`, then rebuild whenever something under `src` changes.
+///
+/// Templates, partials and css live in the content tree, so watching it covers
+/// them too; `template` is for pages without a `_template.html`. `on_rebuild`
+/// runs after every build that changed something. Only returns if the tree
+/// can't be watched at all.
+pub fn watch_site(src: &Path, out: &Path, options: &Options, template: &str, mut on_rebuild: impl FnMut(&BuildReport)) -> Result<(), String> {
+    rebuild(src, out, options, template, &mut on_rebuild);
+    watch_tree(src, Some(out), || rebuild(src, out, options, template, &mut on_rebuild))
+}
+
+/// Call `on_change` after every burst of changes under `root`, skipping those inside `ignore`.
+///
+/// Only returns if the tree can't be watched at all.
+pub fn watch_tree(root: &Path, ignore: Option<&Path>, mut on_change: impl FnMut()) -> Result<(), String> {
+    let watch_root = fs::canonicalize(root).map_err(|err| format!("{}: {}", root.display(), err))?;
+    let (tx, rx) = channel();
+    // inotify first, polling when it isn't available (or we ran out of watches)
+    let mut watcher: Box<dyn Watcher> = match RecommendedWatcher::new(tx.clone(), Config::default()) {
+        Ok(watcher) => Box::new(watcher),
+        Err(_) => Box::new(new_poll_watcher(tx.clone())?),
+    };
+    let mut method = "inotify";
+    if watcher.watch(&watch_root, RecursiveMode::Recursive).is_err() {
+        watcher = Box::new(new_poll_watcher(tx)?);
+        method = "polling";
+        watcher.watch(&watch_root, RecursiveMode::Recursive).map_err(|err| err.to_string())?;
+    }
+    println!("watching {} for changes ({})", watch_root.display(), method);
+
+    loop {
+        // the output directory may have only just been created by the first build
+        let ignore = ignore.and_then(|ignore| fs::canonicalize(ignore).ok());
+        let Ok(first) = rx.recv() else {
+            return Err("file watcher stopped".to_string());
+        };
+        let mut changed = is_relevant(first, ignore.as_deref());
+        // wait for the burst of events from one save to die down
+        while let Ok(event) = rx.recv_timeout(SETTLE_TIME) {
+            changed |= is_relevant(event, ignore.as_deref());
+        }
+        if changed {
+            on_change();
+        }
+    }
+}
+
+fn new_poll_watcher(tx: Sender<notify::Result<Event>>) -> Result<PollWatcher, String> {
+    PollWatcher::new(tx, Config::default().with_poll_interval(POLL_INTERVAL)).map_err(|err| err.to_string())
+}
+
+// our own writes into an output directory inside the content tree don't count
+fn is_relevant(event: notify::Result<Event>, ignore: Option<&Path>) -> bool {
+    let Ok(event) = event else {
+        return false;
+    };
+    if !(event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove()) {
+        return false;
+    }
+    event.paths.iter().any(|path| ignore.is_none_or(|ignore| !path.starts_with(ignore)))
+}
+
+fn rebuild(src: &Path, out: &Path, options: &Options, template: &str, on_rebuild: &mut impl FnMut(&BuildReport)) {
+    let started = Instant::now();
+    // failing pages, the converter crashing on one included, are in the report; this is for the build as a whole
+    match build_site(src, out, options, template) {
+        Ok(report) => {
+            print_changes(&report);
+            if !(report.rendered.is_empty() && report.copied.is_empty() && report.removed.is_empty()) {
+                println!("built in {}ms", started.elapsed().as_millis());
+                on_rebuild(&report);
+            }
+        }
+        Err(err) => eprintln!("error: {}", err),
+    }
+}
+
+fn print_changes(report: &BuildReport) {
+    for path in &report.rendered {
+        println!("  rendered {}", path.display());
+    }
+    for path in &report.copied {
+        println!("  copied   {}", path.display());
+    }
+    for path in &report.removed {
+        println!("  removed  {}", path.display());
+    }
+    for (path, err) in &report.errors {
+        eprintln!("  error    {}: {}", path.display(), err);
+    }
+}
+